@@ -0,0 +1,66 @@
+use cram::diff_drive::Pose;
+use cram::lidar::{Environment, LidarConfig, RayCast};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ndarray::Array2;
+
+// previous fixed-step marching loop, one cell per step, kept as a baseline
+fn march_ray(ray: Pose, max_range: f32, environment: &Environment) -> Option<f32> {
+    let mut dist = 0.;
+    while dist < max_range {
+        let x = ray.x + dist * ray.theta.cos();
+        let y = ray.y + dist * ray.theta.sin();
+        if let Some(coords) = environment.world_to_grid(x, y) {
+            if environment.grid[[coords.y, coords.x]] {
+                return Some(dist);
+            }
+        }
+        dist += environment.geometry.resolution;
+    }
+    None
+}
+
+// square room with a one cell thick diagonal wall across it
+fn room(size: usize) -> Environment {
+    let grid = Array2::from_shape_fn((size, size), |(row, col)| {
+        row == 0 || col == 0 || row == size - 1 || col == size - 1 || row == col
+    });
+    Environment::centred(grid, 0.01)
+}
+
+fn bench_ray_casting(c: &mut Criterion) {
+    let environment = room(600);
+    let origin = Pose {
+        x: -1.5,
+        y: 0.5,
+        theta: 0.,
+    };
+    let config = LidarConfig::default();
+
+    let mut group = c.benchmark_group("ray_casting");
+    group.bench_function("march", |b| {
+        b.iter(|| {
+            for angle in config.beam_angles() {
+                let ray = Pose {
+                    theta: angle,
+                    ..origin
+                };
+                black_box(march_ray(ray, config.max_range, &environment));
+            }
+        })
+    });
+    group.bench_function("dda", |b| {
+        b.iter(|| {
+            for angle in config.beam_angles() {
+                let ray = Pose {
+                    theta: angle,
+                    ..origin
+                };
+                black_box(environment.cast_ray(ray, config.max_range));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_ray_casting);
+criterion_main!(benches);
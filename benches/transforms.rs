@@ -0,0 +1,48 @@
+use cram::transforms::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ndarray::prelude::*;
+use ndarray::stack;
+
+// previous row-by-row implementation, kept as a baseline
+fn transformed_cloud_rowwise(cloud_ref: &Array2<f64>, tmat: &Array2<f64>) -> Array2<f64> {
+    let mut cloud_target = Array2::zeros((cloud_ref.nrows(), cloud_ref.ncols()));
+    for (i, mut row) in cloud_target.axis_iter_mut(Axis(0)).enumerate() {
+        let pt = cloud_ref.slice(s![i, ..]);
+        let new_pt = tmat.dot(&pt);
+        row.assign(&new_pt);
+    }
+    cloud_target
+}
+
+fn sine_cloud(n: usize) -> Array2<f64> {
+    let x = Array::linspace(-3., 3., n);
+    let y = x.map(|t: &f64| t.sin());
+    let h = Array::ones(n);
+    stack![Axis(1), x, y, h]
+}
+
+fn bench_transformed_cloud(c: &mut Criterion) {
+    let tmat = rmat_and_tvec_to_tmat(&angle_to_rmat(std::f64::consts::FRAC_PI_6), &array![2., 0.]);
+    let mut group = c.benchmark_group("transformed_cloud");
+    for n in [128, 1_024, 16_384, 262_144].iter() {
+        let cloud = sine_cloud(*n);
+        group.bench_with_input(BenchmarkId::new("rowwise", n), &cloud, |b, cloud| {
+            b.iter(|| transformed_cloud_rowwise(black_box(cloud), black_box(&tmat)))
+        });
+        group.bench_with_input(BenchmarkId::new("matmul", n), &cloud, |b, cloud| {
+            b.iter(|| transformed_cloud(black_box(cloud), black_box(&tmat)))
+        });
+        group.bench_with_input(BenchmarkId::new("in_place", n), &cloud, |b, cloud| {
+            let mut cloud = cloud.clone();
+            b.iter(|| transform_cloud_in_place(black_box(&mut cloud), black_box(&tmat)))
+        });
+        #[cfg(feature = "rayon")]
+        group.bench_with_input(BenchmarkId::new("parallel", n), &cloud, |b, cloud| {
+            b.iter(|| transformed_cloud_par(black_box(cloud), black_box(&tmat)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_transformed_cloud);
+criterion_main!(benches);
@@ -0,0 +1,90 @@
+// Drives each platform around the same 1 m radius circle for a minute and compares how far its
+// odometry drifts from the true pose.
+// Run with `cargo run --example motion_models`
+use cram::diff_drive::odometry::{OdometryConfig, WheelEncoderConfig};
+use cram::diff_drive::{DiffDriveKinematics, Pose, Robot, RobotCommand, Twist};
+use cram::motion_model::ackermann::{AckermannCommand, AckermannConfig, AckermannRobot};
+use cram::motion_model::mecanum::{HolonomicTwist, MecanumCommand, MecanumConfig, MecanumRobot};
+use cram::motion_model::skid_steer::{SkidSteerConfig, SkidSteerRobot};
+use cram::motion_model::MotionModel;
+
+const TIME_STEP: f32 = 0.05;
+const DURATION: f32 = 60.;
+const SPEED: f32 = 0.3; // m/s
+const RADIUS: f32 = 1.;
+
+fn drive<M: MotionModel>(name: &str, robot: &mut M, command: M::Command) {
+    robot.set_command(command);
+    let steps = (DURATION / TIME_STEP) as usize;
+    for _ in 0..steps {
+        robot.step(TIME_STEP);
+    }
+    let (pose, odometry) = (robot.pose(), robot.odometry());
+    let error = ((pose.x - odometry.x).powi(2) + (pose.y - odometry.y).powi(2)).sqrt();
+    println!(
+        "{:<12} true ({:6.2}, {:6.2}) odometry ({:6.2}, {:6.2}), {:.3} m apart",
+        name, pose.x, pose.y, odometry.x, odometry.y, error
+    );
+}
+
+fn main() {
+    let start = Pose::default();
+    let turn_rate = SPEED / RADIUS;
+
+    let mut diff_drive = Robot::with_odometry(
+        DiffDriveKinematics::default(),
+        OdometryConfig {
+            encoders: Some(WheelEncoderConfig {
+                slip_std_dev: 0.02,
+                ..WheelEncoderConfig::default()
+            }),
+            seed: Some(1),
+            ..OdometryConfig::default()
+        },
+    );
+    let twist = Twist {
+        linear: SPEED,
+        angular: turn_rate,
+    };
+    drive("diff drive", &mut diff_drive, RobotCommand::Twist(twist));
+
+    let mut skid_steer = SkidSteerRobot::new(
+        SkidSteerConfig {
+            icr_factor: 1.6, // calibrated as 1.5
+            seed: Some(2),
+            ..SkidSteerConfig::default()
+        },
+        start,
+    );
+    drive("skid steer", &mut skid_steer, twist);
+
+    let mut ackermann = AckermannRobot::new(
+        AckermannConfig {
+            steering_bias: 0.01,
+            seed: Some(3),
+            ..AckermannConfig::default()
+        },
+        start,
+    );
+    let steering_angle = (ackermann.config.wheelbase / RADIUS).atan();
+    let command = AckermannCommand {
+        speed: SPEED,
+        steering_angle,
+    };
+    drive("ackermann", &mut ackermann, command);
+
+    // the mecanum base drives the circle sideways, with its back to the centre
+    let mut mecanum = MecanumRobot::new(
+        MecanumConfig {
+            seed: Some(4),
+            ..MecanumConfig::default()
+        },
+        start,
+    );
+    let command = MecanumCommand::Twist(HolonomicTwist {
+        forward: 0.,
+        lateral: SPEED,
+        angular: turn_rate,
+    });
+    drive("mecanum", &mut mecanum, command);
+}
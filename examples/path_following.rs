@@ -0,0 +1,80 @@
+// Drives the robot along a route through the floor plan with each path-following controller, then
+// puts a person in the way of a straight route across an empty room, which only the dynamic window
+// approach steers around.
+// Run with `cargo run --release --example path_following`
+use cram::controller::dwa::{Dwa, DwaConfig};
+use cram::controller::pid::{PidConfig, PidHeading};
+use cram::controller::pure_pursuit::{PurePursuit, PurePursuitConfig};
+use cram::controller::{follow, PathController};
+use cram::diff_drive::{DiffDriveKinematics, Pose, Robot};
+use cram::lidar::dynamic::{DynamicObject, Shape, Trajectory};
+use cram::lidar::map::{load_image_map, OccupancyRule};
+use cram::lidar::Environment;
+use ndarray::Array2;
+use std::path::Path;
+
+const TIME_STEP: f32 = 0.05;
+const TIME_LIMIT: f32 = 120.;
+
+fn run(name: &str, controller: &mut dyn PathController, start: Pose, environment: &Environment) {
+    let mut robot = Robot::new(DiffDriveKinematics::default());
+    robot.state.pose = start;
+    let result = follow(&mut robot, controller, environment, TIME_STEP, TIME_LIMIT);
+    let pose = robot.state.pose;
+    println!(
+        "{:<14} reached: {:<5} after {:5.1} s, {:4} steps in contact, ending at ({:.2}, {:.2})",
+        name, result.reached, result.time, result.collisions, pose.x, pose.y
+    );
+}
+
+fn run_all(environment: &Environment, waypoints: Vec<[f32; 2]>, start: Pose) {
+    let mut pure_pursuit = PurePursuit::new(waypoints.clone(), PurePursuitConfig::default());
+    run("pure pursuit", &mut pure_pursuit, start, environment);
+    let mut pid = PidHeading::new(waypoints.clone(), PidConfig::default());
+    run("PID heading", &mut pid, start, environment);
+    let mut dwa = Dwa::new(waypoints, environment, DwaConfig::default());
+    run("DWA", &mut dwa, start, environment);
+}
+
+fn main() {
+    // up the hallway, to its end and back, then return to the start
+    println!("floor plan");
+    let floor = load_image_map(
+        Path::new("assets/maps/floor.jpg"),
+        OccupancyRule::DarkerThan(40),
+        0.01,
+        None,
+    )
+    .expect("failed to load map");
+    let start = Pose {
+        x: 2.6,
+        y: -0.8,
+        theta: std::f32::consts::FRAC_PI_2,
+    };
+    let waypoints = vec![[2.7, 0.3], [4.2, 0.3], [2.7, 0.3], [2.6, -0.8]];
+    run_all(&floor, waypoints, start);
+
+    // 4 x 3 m room walled in by 5 cm thick walls
+    println!("room with a person standing near the route");
+    let mut grid = Array2::from_elem((300, 400), false);
+    for ((row, col), cell) in grid.indexed_iter_mut() {
+        *cell = !(5..295).contains(&row) || !(5..395).contains(&col);
+    }
+    let mut room = Environment::centred(grid, 0.01);
+    room.dynamic_objects.push(DynamicObject::new(
+        Shape::Circle { radius: 0.2 },
+        Pose {
+            x: 0.8,
+            y: 0.65,
+            theta: 0.,
+        },
+        Trajectory::Stationary,
+        1,
+    ));
+    let start = Pose {
+        x: -1.5,
+        y: 0.5,
+        theta: 0.,
+    };
+    run_all(&room, vec![[1.5, 0.5]], start);
+}
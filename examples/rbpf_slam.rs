@@ -0,0 +1,136 @@
+// Headless RBPF SLAM on the floor plan: drives a simulated robot along the hallway and into the
+// living area with noisy odometry, then saves the best particle's map.
+// Run with `cargo run --release --example rbpf_slam [output directory]`
+use cram::diff_drive::{normalise_angle, OdometryMotionModel, Pose};
+use cram::lidar::map::{load_image_map, OccupancyRule};
+use cram::lidar::{Dimension, GridGeometry, Lidar, LidarConfig};
+use cram::occupancy_grid::map_file::{save_png, save_ros_map};
+use cram::rbpf_slam::{RbpfConfig, RbpfSlam};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::path::{Path, PathBuf};
+
+const TIME_STEP: f32 = 0.1;
+const SPEED: f32 = 0.3; // m/s
+const TURN_RATE: f32 = 1.; // rad/s
+
+fn main() {
+    let output_dir = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target"));
+    let environment = load_image_map(
+        Path::new("assets/maps/floor.jpg"),
+        OccupancyRule::DarkerThan(40),
+        0.01,
+        None,
+    )
+    .expect("run from the repository root");
+
+    let mut lidar = Lidar::new(LidarConfig {
+        beam_count: 360,
+        max_range: 8.,
+        range_noise_std_dev: 0.01,
+        seed: Some(1),
+        ..LidarConfig::default()
+    });
+    let geometry = GridGeometry::centred(
+        Dimension {
+            width: 260,
+            height: 140,
+        },
+        0.05,
+    );
+    let waypoints = [
+        (2.8, 0.29),
+        (4.4, 0.84),
+        (2.8, 0.29),
+        (-3.3, 0.19),
+        (-2., 0.2),
+    ];
+    let mut pose = Pose {
+        x: -3.3,
+        y: 0.19,
+        theta: 0.,
+    };
+    let mut slam = RbpfSlam::new(
+        geometry,
+        pose,
+        RbpfConfig {
+            seed: Some(2),
+            ..RbpfConfig::default()
+        },
+    );
+
+    // odometry drifts from the true pose with the errors of the odometry motion model
+    let odometry_error = OdometryMotionModel {
+        alpha1: 0.05,
+        alpha2: 0.05,
+        alpha3: 0.05,
+        alpha4: 0.05,
+    };
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut odometry = pose;
+    let mut updates = 0;
+    let mut time = 0.;
+    for (goal_x, goal_y) in waypoints.iter() {
+        loop {
+            let dx = goal_x - pose.x;
+            let dy = goal_y - pose.y;
+            if (dx * dx + dy * dy).sqrt() < 0.05 {
+                break;
+            }
+            let heading_error = normalise_angle(dy.atan2(dx) - pose.theta);
+            let turn = heading_error.clamp(-TURN_RATE * TIME_STEP, TURN_RATE * TIME_STEP);
+            let forward = if heading_error.abs() < 0.3 {
+                (SPEED * TIME_STEP).min((dx * dx + dy * dy).sqrt())
+            } else {
+                0.
+            };
+            let next = Pose {
+                x: pose.x + forward * (pose.theta + turn).cos(),
+                y: pose.y + forward * (pose.theta + turn).sin(),
+                theta: pose.theta + turn,
+            };
+            odometry = odometry_error.sample(odometry, pose, next, &mut rng);
+            pose = next;
+            time += TIME_STEP as f64;
+
+            let scan = lidar.scan(pose, time, &environment);
+            if slam.update(odometry, &scan) {
+                updates += 1;
+            }
+        }
+    }
+
+    let estimate = slam.pose();
+    let error = |p: Pose| ((p.x - pose.x).powi(2) + (p.y - pose.y).powi(2)).sqrt();
+    println!("{} filter updates over {:.0} s", updates, time);
+    println!("true pose      {:?}", pose);
+    println!(
+        "slam estimate  {:?}, {:.3} m off",
+        estimate,
+        error(estimate)
+    );
+    println!(
+        "odometry       {:?}, {:.3} m off",
+        odometry,
+        error(odometry)
+    );
+    let tiles: usize = slam.particles.iter().map(|p| p.map.allocated_tiles()).sum();
+    println!(
+        "{} map tiles in use by {} particles, {} without sharing",
+        slam.unique_tiles(),
+        slam.particles.len(),
+        tiles
+    );
+
+    let map = slam.map();
+    std::fs::create_dir_all(&output_dir).unwrap();
+    save_ros_map(&map, &output_dir.join("rbpf_map.yaml")).unwrap();
+    save_png(&map, None, &output_dir.join("rbpf_map.png")).unwrap();
+    println!(
+        "map saved to {}",
+        output_dir.join("rbpf_map.yaml").display()
+    );
+}
@@ -0,0 +1,298 @@
+// Controllers that drive a diff_drive::Robot through a list of waypoints, replacing the arrow keys
+// with something reproducible. Each turns the robot's pose and velocity into a twist command.
+use crate::diff_drive::{normalise_angle, Pose, Robot, Twist};
+use crate::lidar::Environment;
+
+pub mod dwa;
+pub mod pid;
+pub mod pure_pursuit;
+
+pub trait PathController {
+    // Velocity to command a robot at pose, currently moving at velocity, for the next time_step
+    // seconds. None once the robot has reached the final waypoint.
+    fn command(
+        &mut self,
+        pose: Pose,
+        velocity: Twist,
+        time_step: f32,
+        environment: &Environment,
+    ) -> Option<Twist>;
+}
+
+// Waypoints joined by straight lines, starting from wherever the robot is when it first asks
+// for a lookahead point. Remembers how far along the robot has got so that paths which double
+// back are followed in order.
+#[derive(Clone, Debug)]
+pub struct Path {
+    pub waypoints: Vec<[f32; 2]>, // world positions
+    pub waypoint_tolerance: f32,  // metres within which a waypoint counts as reached
+    segment: usize,               // index of the waypoint at the start of the current segment
+    started: bool,
+}
+
+impl Path {
+    pub fn new(waypoints: Vec<[f32; 2]>, waypoint_tolerance: f32) -> Self {
+        Path {
+            waypoints,
+            waypoint_tolerance,
+            segment: 0,
+            started: false,
+        }
+    }
+
+    pub fn goal(&self) -> Option<[f32; 2]> {
+        self.waypoints.last().copied()
+    }
+
+    // Distance left to drive, straight to the end of the current segment then along the rest of
+    // the path. Paths that loop back to their start only count as finished on the last segment.
+    pub fn remaining(&self, pose: Pose) -> f32 {
+        let next = (self.segment + 1).min(self.waypoints.len().saturating_sub(1));
+        let [x, y] = match self.waypoints.get(next) {
+            Some(waypoint) => *waypoint,
+            None => return 0.,
+        };
+        let rest: f32 = self.waypoints[next..]
+            .windows(2)
+            .map(|pair| {
+                ((pair[1][0] - pair[0][0]).powi(2) + (pair[1][1] - pair[0][1]).powi(2)).sqrt()
+            })
+            .sum();
+        ((x - pose.x).powi(2) + (y - pose.y).powi(2)).sqrt() + rest
+    }
+
+    // Point lookahead metres further along the path than the point on it nearest the robot, or
+    // the goal if the path ends first. The robot moves on to the next segment once it reaches or
+    // passes the end of the current one, or comes nearer the next. The point stops at waypoints
+    // where the path turns back by more than a right angle, so the robot drives up to them before
+    // turning.
+    pub fn lookahead_point(&mut self, pose: Pose, lookahead: f32) -> Option<[f32; 2]> {
+        if !self.started && !self.waypoints.is_empty() {
+            self.waypoints.insert(0, [pose.x, pose.y]);
+            self.started = true;
+        }
+        if self.waypoints.is_empty() {
+            return None;
+        }
+        let last_segment = self.waypoints.len() - 2;
+        while self.segment < last_segment {
+            let (t, distance) = self.project(self.segment, pose.x, pose.y);
+            let (_, next_distance) = self.project(self.segment + 1, pose.x, pose.y);
+            let [x, y] = self.waypoints[self.segment + 1];
+            let reached =
+                ((x - pose.x).powi(2) + (y - pose.y).powi(2)).sqrt() < self.waypoint_tolerance;
+            if t < 1. && distance <= next_distance && !reached {
+                break;
+            }
+            self.segment += 1;
+        }
+
+        // walk lookahead metres along the path from the nearest point
+        let (along, _) = self.project(self.segment, pose.x, pose.y);
+        let mut remaining = lookahead;
+        for segment in self.segment..=last_segment {
+            let [x0, y0] = self.waypoints[segment];
+            let [x1, y1] = self.waypoints[segment + 1];
+            let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+            let start = if segment == self.segment {
+                along * length
+            } else {
+                0.
+            };
+            if start + remaining <= length {
+                let t = (start + remaining) / length;
+                return Some([x0 + t * (x1 - x0), y0 + t * (y1 - y0)]);
+            }
+            remaining -= length - start;
+            if segment < last_segment {
+                let [x2, y2] = self.waypoints[segment + 2];
+                if (x1 - x0) * (x2 - x1) + (y1 - y0) * (y2 - y1) < 0. {
+                    return Some([x1, y1]);
+                }
+            }
+        }
+        self.goal()
+    }
+
+    // fraction along a segment of the point on it nearest (x, y), and the distance to that point
+    fn project(&self, segment: usize, x: f32, y: f32) -> (f32, f32) {
+        let [x0, y0] = self.waypoints[segment];
+        let [x1, y1] = self.waypoints[segment + 1];
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let length_sq = dx * dx + dy * dy;
+        let t = if length_sq > 0. {
+            (((x - x0) * dx + (y - y0) * dy) / length_sq).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let (px, py) = (x0 + t * dx, y0 + t * dy);
+        (t, ((x - px).powi(2) + (y - py).powi(2)).sqrt())
+    }
+}
+
+// heading of a point relative to the robot, in (-pi, pi]
+pub fn bearing(pose: Pose, [x, y]: [f32; 2]) -> f32 {
+    normalise_angle((y - pose.y).atan2(x - pose.x) - pose.theta)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FollowResult {
+    pub reached: bool,
+    pub time: f32,
+    pub collisions: usize, // steps on which the robot touched something
+}
+
+// Drive a robot with a controller from its true pose until the controller reports the goal
+// reached or time_limit seconds pass, stepping through the environment with collisions
+pub fn follow<C: PathController + ?Sized>(
+    robot: &mut Robot,
+    controller: &mut C,
+    environment: &Environment,
+    time_step: f32,
+    time_limit: f32,
+) -> FollowResult {
+    let mut time = 0.;
+    let mut collisions = 0;
+    while time < time_limit {
+        let (linear, angular) = robot.velocity();
+        let velocity = Twist { linear, angular };
+        match controller.command(robot.state.pose, velocity, time_step, environment) {
+            Some(twist) => robot.set_twist(twist.linear, twist.angular),
+            None => {
+                robot.set_twist(0., 0.);
+                return FollowResult {
+                    reached: true,
+                    time,
+                    collisions,
+                };
+            }
+        }
+        if robot.step_in(time_step, environment).is_some() {
+            collisions += 1;
+        }
+        time += time_step;
+    }
+    FollowResult {
+        reached: false,
+        time,
+        collisions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dwa::{Dwa, DwaConfig};
+    use super::pid::{PidConfig, PidHeading};
+    use super::pure_pursuit::{PurePursuit, PurePursuitConfig};
+    use super::*;
+    use crate::diff_drive::DiffDriveKinematics;
+    use ndarray::Array2;
+
+    const TIME_STEP: f32 = 0.05;
+    const TIME_LIMIT: f32 = 90.;
+
+    // 4 x 3 m room with 10 cm walls, and an optional 0.5 m square box in the middle
+    fn room(with_box: bool) -> Environment {
+        let mut grid = Array2::from_elem((60, 80), false);
+        for ((row, col), cell) in grid.indexed_iter_mut() {
+            let wall = !(2..58).contains(&row) || !(2..78).contains(&col);
+            let obstacle = (25..35).contains(&row) && (35..45).contains(&col);
+            *cell = wall || with_box && obstacle;
+        }
+        Environment::centred(grid, 0.05)
+    }
+
+    fn assert_reaches(
+        controller: &mut dyn PathController,
+        start: Pose,
+        goal: [f32; 2],
+        goal_tolerance: f32,
+        environment: &Environment,
+    ) {
+        let mut robot = Robot::new(DiffDriveKinematics::default());
+        robot.state.pose = start;
+        let result = follow(&mut robot, controller, environment, TIME_STEP, TIME_LIMIT);
+        let pose = robot.state.pose;
+        assert!(result.reached, "{:?} ending at {:?}", result, pose);
+        assert_eq!(result.collisions, 0);
+        let distance = ((pose.x - goal[0]).powi(2) + (pose.y - goal[1]).powi(2)).sqrt();
+        assert!(distance < goal_tolerance, "{} m from the goal", distance);
+    }
+
+    // along the room, up and back to the middle, starting off facing away from the route
+    fn route() -> (Pose, Vec<[f32; 2]>) {
+        let start = Pose {
+            x: -1.4,
+            y: -0.9,
+            theta: 2.5,
+        };
+        (start, vec![[1.2, -0.9], [1.2, 0.9], [0., 0.9]])
+    }
+
+    #[test]
+    fn pure_pursuit_follows_route() {
+        let (start, waypoints) = route();
+        let config = PurePursuitConfig::default();
+        let goal = *waypoints.last().unwrap();
+        let mut controller = PurePursuit::new(waypoints, config);
+        assert_reaches(
+            &mut controller,
+            start,
+            goal,
+            config.goal_tolerance,
+            &room(false),
+        );
+    }
+
+    #[test]
+    fn pid_heading_follows_route() {
+        let (start, waypoints) = route();
+        let config = PidConfig::default();
+        let goal = *waypoints.last().unwrap();
+        let mut controller = PidHeading::new(waypoints, config);
+        assert_reaches(
+            &mut controller,
+            start,
+            goal,
+            config.goal_tolerance,
+            &room(false),
+        );
+    }
+
+    #[test]
+    fn dwa_follows_route() {
+        let (start, waypoints) = route();
+        let environment = room(false);
+        let config = DwaConfig::default();
+        let goal = *waypoints.last().unwrap();
+        let mut controller = Dwa::new(waypoints, &environment, config);
+        assert_reaches(
+            &mut controller,
+            start,
+            goal,
+            config.goal_tolerance,
+            &environment,
+        );
+    }
+
+    #[test]
+    fn dwa_steers_round_obstacle_on_route() {
+        let environment = room(true);
+        let start = Pose {
+            x: -1.5,
+            y: 0.,
+            theta: 0.,
+        };
+        let goal = [1.5, 0.];
+        assert!(environment.is_blocked(0., 0.));
+        let config = DwaConfig::default();
+        let mut controller = Dwa::new(vec![goal], &environment, config);
+        assert_reaches(
+            &mut controller,
+            start,
+            goal,
+            config.goal_tolerance,
+            &environment,
+        );
+    }
+}
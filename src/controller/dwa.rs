@@ -0,0 +1,200 @@
+// Dynamic window approach from The Dynamic Window Approach to Collision Avoidance by D. Fox et al.
+// Velocities the robot can reach within one time step are rolled out for a short horizon, those
+// that would hit an obstacle or couldn't stop in time are dropped, and the rest are scored on
+// how close they end to a point ahead on the path, clearance from obstacles and speed.
+use super::{bearing, Path, PathController};
+use crate::diff_drive::{integrate, Pose, Twist, VelocityLimits};
+use crate::distance_transform::distance_transform;
+use crate::lidar::Environment;
+use ndarray::Array2;
+use std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct DwaConfig {
+    pub limits: VelocityLimits, // should match the robot's, as they set the window
+    pub robot_radius: f32,      // metres, the footprint's bounding radius plus a safety margin
+    pub linear_samples: usize,
+    pub angular_samples: usize,
+    pub horizon: f32,       // seconds each velocity is rolled out for
+    pub rollout_step: f32,  // seconds between checked poses of a rollout
+    pub lookahead: f32,     // metres along the path of the point driven towards
+    pub max_clearance: f32, // metres, clearance beyond this scores no better
+    pub progress_weight: f32,
+    pub clearance_weight: f32,
+    pub velocity_weight: f32,
+    pub waypoint_tolerance: f32, // metres within which an intermediate waypoint counts as passed
+    pub goal_tolerance: f32,     // metres
+}
+
+impl Default for DwaConfig {
+    fn default() -> Self {
+        DwaConfig {
+            limits: VelocityLimits {
+                max_linear_vel: 0.3,
+                ..VelocityLimits::default()
+            },
+            robot_radius: 0.22,
+            linear_samples: 11,
+            angular_samples: 21,
+            horizon: 1.5,
+            rollout_step: 0.1,
+            lookahead: 0.5,
+            max_clearance: 0.5,
+            progress_weight: 1.,
+            clearance_weight: 0.3,
+            velocity_weight: 0.3,
+            waypoint_tolerance: 0.1,
+            goal_tolerance: 0.05,
+        }
+    }
+}
+
+pub struct Dwa {
+    pub config: DwaConfig,
+    pub path: Path,
+    distances: Array2<f32>, // metres from each cell of the static grid to the nearest occupied one
+}
+
+impl Dwa {
+    // Precomputes the distance transform of the environment's static grid. It measures between
+    // cell centres, so it is reduced by the furthest a point can be from its cell centre and by the
+    // half cell margin footprint contacts allow, to never overstate the clearance on coarse grids.
+    pub fn new(waypoints: Vec<[f32; 2]>, environment: &Environment, config: DwaConfig) -> Self {
+        let resolution = environment.geometry.resolution;
+        let margin = resolution * (FRAC_1_SQRT_2 + 0.5);
+        Dwa {
+            config,
+            path: Path::new(waypoints, config.waypoint_tolerance),
+            distances: distance_transform(&environment.grid, resolution)
+                .mapv(|d| (d - margin).max(0.)),
+        }
+    }
+
+    // metres from a point to the nearest obstacle, zero outside the map
+    fn clearance(&self, x: f32, y: f32, environment: &Environment) -> f32 {
+        let static_clearance = match environment.world_to_grid(x, y) {
+            Some(coord) => self.distances[[coord.y, coord.x]],
+            None => 0.,
+        };
+        environment
+            .dynamic_objects
+            .iter()
+            .map(|object| {
+                let distance = ((object.pose.x - x).powi(2) + (object.pose.y - y).powi(2)).sqrt();
+                (distance - object.bounding_radius()).max(0.)
+            })
+            .fold(static_clearance, f32::min)
+    }
+
+    // smallest clearance of the robot's edge along the rollout of a velocity, negative on contact,
+    // giving up once it falls below floor
+    fn rollout_clearance(
+        &self,
+        pose: Pose,
+        linear: f32,
+        angular: f32,
+        floor: f32,
+        environment: &Environment,
+    ) -> f32 {
+        let config = self.config;
+        let steps = (config.horizon / config.rollout_step).ceil() as usize;
+        let mut clearance = f32::INFINITY;
+        for i in 1..=steps {
+            let time = (i as f32 * config.rollout_step).min(config.horizon);
+            let pose = integrate(pose, linear, angular, time);
+            clearance =
+                clearance.min(self.clearance(pose.x, pose.y, environment) - config.robot_radius);
+            if clearance < floor {
+                break;
+            }
+        }
+        clearance
+    }
+}
+
+impl PathController for Dwa {
+    fn command(
+        &mut self,
+        pose: Pose,
+        velocity: Twist,
+        time_step: f32,
+        environment: &Environment,
+    ) -> Option<Twist> {
+        let config = self.config;
+        let limits = config.limits;
+        let target = self.path.lookahead_point(pose, config.lookahead)?;
+        let remaining = self.path.remaining(pose);
+        if remaining < config.goal_tolerance {
+            return None;
+        }
+
+        // velocities reachable within the time step, forwards only as there is no rear sensing,
+        // and no faster than will stop on the goal
+        let max_linear = limits
+            .max_linear_vel
+            .min((2. * limits.max_linear_accel * remaining).sqrt());
+        let linear_min = (velocity.linear - limits.max_linear_accel * time_step).max(0.);
+        let linear_max = (velocity.linear + limits.max_linear_accel * time_step).min(max_linear);
+        let angular_min =
+            (velocity.angular - limits.max_angular_accel * time_step).max(-limits.max_angular_vel);
+        let angular_max =
+            (velocity.angular + limits.max_angular_accel * time_step).min(limits.max_angular_vel);
+        let max_reach = limits.max_linear_vel * config.horizon;
+        let sample = |min: f32, max: f32, count: usize, i: usize| {
+            if count < 2 || max <= min {
+                min
+            } else {
+                min + (max - min) * i as f32 / (count - 1) as f32
+            }
+        };
+
+        // a robot already closer to an obstacle than its radius, say from one moving into it, may
+        // take any velocity that doesn't bring it closer still
+        let start_clearance = self.clearance(pose.x, pose.y, environment) - config.robot_radius;
+        let min_clearance = start_clearance.min(0.);
+
+        let mut best: Option<(f32, Twist)> = None;
+        for i in 0..config.linear_samples {
+            let linear = sample(
+                linear_min,
+                linear_max.max(linear_min),
+                config.linear_samples,
+                i,
+            );
+            for j in 0..config.angular_samples {
+                let angular = sample(angular_min, angular_max, config.angular_samples, j);
+                let clearance =
+                    self.rollout_clearance(pose, linear, angular, min_clearance, environment);
+                // admissible only if the robot can brake to a stop before reaching the obstacle,
+                // or at least ends up no closer to it than it is now
+                let braking = linear * linear / (2. * limits.max_linear_accel);
+                if clearance < min_clearance || (clearance < braking && clearance < start_clearance)
+                {
+                    continue;
+                }
+                let end = integrate(pose, linear, angular, config.horizon);
+                let distance = ((target[0] - end.x).powi(2) + (target[1] - end.y).powi(2)).sqrt();
+                let progress = 1. - distance / (config.lookahead + max_reach);
+                let score = config.progress_weight * progress
+                    + config.clearance_weight * clearance.min(config.max_clearance)
+                        / config.max_clearance
+                    + config.velocity_weight * linear / limits.max_linear_vel;
+                let better = match best {
+                    Some((best_score, _)) => score > best_score,
+                    None => true,
+                };
+                if better {
+                    best = Some((score, Twist { linear, angular }));
+                }
+            }
+        }
+        // with every velocity blocked, stop and turn towards the target to look for a way out
+        Some(match best {
+            Some((_, twist)) => twist,
+            None => Twist {
+                linear: 0.,
+                angular: limits.max_angular_vel.copysign(bearing(pose, target)),
+            },
+        })
+    }
+}
@@ -0,0 +1,100 @@
+// Heads straight for each waypoint in turn, steering with a PID controller on the heading error
+// and driving forwards only as fast as the robot is pointing the right way
+use super::{bearing, PathController};
+use crate::diff_drive::{normalise_angle, Pose, Twist};
+use crate::lidar::Environment;
+
+#[derive(Copy, Clone, Debug)]
+pub struct PidConfig {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub max_integral: f32, // limit on the accumulated heading error, in rad s
+    pub speed: f32,        // m/s
+    pub max_angular_vel: f32,
+    pub waypoint_tolerance: f32, // metres within which an intermediate waypoint counts as passed
+    pub goal_tolerance: f32,     // metres
+}
+
+impl Default for PidConfig {
+    fn default() -> Self {
+        PidConfig {
+            kp: 2.,
+            ki: 0.1,
+            kd: 0.1,
+            max_integral: 0.5,
+            speed: 0.3,
+            max_angular_vel: 1.5,
+            waypoint_tolerance: 0.15,
+            goal_tolerance: 0.05,
+        }
+    }
+}
+
+pub struct PidHeading {
+    pub config: PidConfig,
+    pub waypoints: Vec<[f32; 2]>,
+    waypoint: usize, // index of the waypoint being driven to
+    integral: f32,
+    previous_error: Option<f32>,
+}
+
+impl PidHeading {
+    pub fn new(waypoints: Vec<[f32; 2]>, config: PidConfig) -> Self {
+        PidHeading {
+            config,
+            waypoints,
+            waypoint: 0,
+            integral: 0.,
+            previous_error: None,
+        }
+    }
+}
+
+impl PathController for PidHeading {
+    fn command(
+        &mut self,
+        pose: Pose,
+        _velocity: Twist,
+        time_step: f32,
+        _environment: &Environment,
+    ) -> Option<Twist> {
+        let config = self.config;
+        let waypoints = &self.waypoints;
+        let distance = |[x, y]: [f32; 2]| ((x - pose.x).powi(2) + (y - pose.y).powi(2)).sqrt();
+        while self.waypoint + 1 < waypoints.len()
+            && distance(waypoints[self.waypoint]) < config.waypoint_tolerance
+        {
+            self.waypoint += 1;
+            self.integral = 0.;
+            self.previous_error = None;
+        }
+        let target = *waypoints.get(self.waypoint)?;
+        let remaining = distance(target);
+        if self.waypoint + 1 == waypoints.len() && remaining < config.goal_tolerance {
+            return None;
+        }
+
+        let error = bearing(pose, target);
+        self.integral =
+            (self.integral + error * time_step).clamp(-config.max_integral, config.max_integral);
+        let derivative = match self.previous_error {
+            Some(previous) if time_step > 0. => normalise_angle(error - previous) / time_step,
+            _ => 0.,
+        };
+        self.previous_error = Some(error);
+        let angular = (config.kp * error + config.ki * self.integral + config.kd * derivative)
+            .clamp(-config.max_angular_vel, config.max_angular_vel);
+
+        // the last waypoint is approached slowly enough to stop on it
+        let speed = if self.waypoint + 1 == waypoints.len() {
+            config.speed.min(remaining)
+        } else {
+            config.speed
+        };
+        Some(Twist {
+            linear: speed * error.cos().max(0.),
+            angular,
+        })
+    }
+}
@@ -0,0 +1,79 @@
+// Pure pursuit, from Implementation of the Pure Pursuit Path Tracking Algorithm by R. C. Coulter.
+// The robot steers along the arc through a point lookahead metres ahead on the path, slowing
+// down as it closes on the goal.
+use super::{bearing, Path, PathController};
+use crate::diff_drive::{Pose, Twist};
+use crate::lidar::Environment;
+
+#[derive(Copy, Clone, Debug)]
+pub struct PurePursuitConfig {
+    pub lookahead: f32, // metres
+    pub speed: f32,     // m/s
+    pub max_angular_vel: f32,
+    pub waypoint_tolerance: f32, // metres within which an intermediate waypoint counts as passed
+    pub goal_tolerance: f32,     // metres
+}
+
+impl Default for PurePursuitConfig {
+    fn default() -> Self {
+        PurePursuitConfig {
+            lookahead: 0.4,
+            speed: 0.3,
+            max_angular_vel: 1.5,
+            waypoint_tolerance: 0.1,
+            goal_tolerance: 0.05,
+        }
+    }
+}
+
+pub struct PurePursuit {
+    pub config: PurePursuitConfig,
+    pub path: Path,
+}
+
+impl PurePursuit {
+    pub fn new(waypoints: Vec<[f32; 2]>, config: PurePursuitConfig) -> Self {
+        PurePursuit {
+            config,
+            path: Path::new(waypoints, config.waypoint_tolerance),
+        }
+    }
+}
+
+impl PathController for PurePursuit {
+    fn command(
+        &mut self,
+        pose: Pose,
+        _velocity: Twist,
+        _time_step: f32,
+        _environment: &Environment,
+    ) -> Option<Twist> {
+        let config = self.config;
+        let target = self.path.lookahead_point(pose, config.lookahead)?;
+        let remaining = self.path.remaining(pose);
+        if remaining < config.goal_tolerance {
+            return None;
+        }
+        let heading_error = bearing(pose, target);
+
+        // turn on the spot towards a target behind the robot, where the arc would be a wide loop
+        if heading_error.abs() > std::f32::consts::FRAC_PI_2 {
+            return Some(Twist {
+                linear: 0.,
+                angular: config.max_angular_vel.copysign(heading_error),
+            });
+        }
+        // the arc through the target has curvature 2 sin(alpha) / distance
+        let distance = ((target[0] - pose.x).powi(2) + (target[1] - pose.y).powi(2)).sqrt();
+        let curvature = 2. * heading_error.sin() / distance.max(1e-3);
+        let mut linear = config.speed.min(remaining);
+        if (linear * curvature).abs() > config.max_angular_vel {
+            // keep to the arc by slowing down rather than turning less
+            linear = config.max_angular_vel / curvature.abs();
+        }
+        Some(Twist {
+            linear,
+            angular: linear * curvature,
+        })
+    }
+}
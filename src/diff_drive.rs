@@ -1,19 +1,284 @@
-#[derive(Debug)]
+use collision::{CollisionResponse, Footprint};
+use odometry::{Odometry, OdometryConfig};
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use std::f32::consts::PI;
+use std::fmt;
+
+pub mod collision;
+pub mod odometry;
+
+#[derive(Debug, Copy, Clone)]
 pub enum RobotCommand {
     TurnLeft,
     TurnRight,
     Forward,
     Back,
     Stop,
+    Twist(Twist),
+    WheelVelocity { left: f32, right: f32 }, // rad/s of each wheel
+}
+
+// velocity of the base in its own frame, as in a ROS geometry_msgs/Twist
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Twist {
+    pub linear: f32,  // m/s forwards
+    pub angular: f32, // rad/s anticlockwise
+}
+
+// Limits the robot applies to every command. Accelerations are in the base frame, and wheel
+// speeds over the limit are scaled down together so the robot keeps its curvature. Every limit
+// must be a non-negative number, which Robot::set_limits checks.
+#[derive(Debug, Copy, Clone)]
+pub struct VelocityLimits {
+    pub max_linear_vel: f32,
+    pub max_angular_vel: f32,
+    pub max_linear_accel: f32,
+    pub max_angular_accel: f32,
+    pub max_wheel_vel: f32, // rad/s
+}
+
+impl Default for VelocityLimits {
+    fn default() -> Self {
+        VelocityLimits {
+            max_linear_vel: 0.5,
+            max_angular_vel: 2.,
+            max_linear_accel: 1.,
+            max_angular_accel: 4.,
+            max_wheel_vel: 16.,
+        }
+    }
+}
+
+impl VelocityLimits {
+    pub fn validate(&self) -> Result<(), InvalidVelocityLimit> {
+        let limits = [
+            ("max_linear_vel", self.max_linear_vel),
+            ("max_angular_vel", self.max_angular_vel),
+            ("max_linear_accel", self.max_linear_accel),
+            ("max_angular_accel", self.max_angular_accel),
+            ("max_wheel_vel", self.max_wheel_vel),
+        ];
+        let invalid = limits
+            .iter()
+            .find(|(_, value)| value.is_nan() || *value < 0.);
+        match invalid {
+            Some(&(name, value)) => Err(InvalidVelocityLimit { name, value }),
+            None => Ok(()),
+        }
+    }
+}
+
+// a velocity limit that is negative or NaN
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InvalidVelocityLimit {
+    pub name: &'static str,
+    pub value: f32,
 }
 
+impl fmt::Display for InvalidVelocityLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} must be a non-negative number, not {}",
+            self.name, self.value
+        )
+    }
+}
+
+impl std::error::Error for InvalidVelocityLimit {}
+
+// Dimensions of a differential drive base, whose wheels sit track_width apart on a common axle
 #[derive(Debug, Copy, Clone)]
+pub struct DiffDriveKinematics {
+    pub wheel_radius: f32, // metres
+    pub track_width: f32,  // metres between the wheels' contact points
+}
+
+impl Default for DiffDriveKinematics {
+    fn default() -> Self {
+        DiffDriveKinematics {
+            wheel_radius: 0.05,
+            track_width: 0.3,
+        }
+    }
+}
+
+impl DiffDriveKinematics {
+    // linear (m/s) and angular (rad/s) velocity of the base from its wheel speeds in rad/s
+    pub fn body_velocity(&self, left: f32, right: f32) -> (f32, f32) {
+        body_velocity(
+            left * self.wheel_radius,
+            right * self.wheel_radius,
+            self.track_width,
+        )
+    }
+
+    // wheel speeds in rad/s that give the base a linear and angular velocity
+    pub fn wheel_velocities(&self, linear_vel: f32, angular_vel: f32) -> (f32, f32) {
+        let (left, right) = wheel_velocities(linear_vel, angular_vel, self.track_width);
+        (left / self.wheel_radius, right / self.wheel_radius)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
 pub struct Pose {
     pub x: f32,
     pub y: f32,
     pub theta: f32,
 }
 
+impl Pose {
+    // pose of a frame given relative to this one, e.g. a sensor mounted on the robot
+    pub fn compose(&self, relative: Pose) -> Pose {
+        let (s, c) = self.theta.sin_cos();
+        Pose {
+            x: self.x + c * relative.x - s * relative.y,
+            y: self.y + s * relative.x + c * relative.y,
+            theta: self.theta + relative.theta,
+        }
+    }
+
+    // pose that undoes this one, so that pose.compose(pose.inverse()) is the identity
+    pub fn inverse(&self) -> Pose {
+        let (s, c) = self.theta.sin_cos();
+        Pose {
+            x: -c * self.x - s * self.y,
+            y: s * self.x - c * self.y,
+            theta: -self.theta,
+        }
+    }
+
+    // linear interpolation, fraction 0 gives self and 1 gives other, heading takes the shorter way
+    // round
+    pub fn interpolate(&self, other: Pose, fraction: f32) -> Pose {
+        let dtheta = normalise_angle(other.theta - self.theta);
+        Pose {
+            x: self.x + fraction * (other.x - self.x),
+            y: self.y + fraction * (other.y - self.y),
+            theta: self.theta + fraction * dtheta,
+        }
+    }
+}
+
+// Odometry motion model from Probabilistic Robotics by S. Thrun et al. (ch. 5.4). The motion
+// between two odometry readings is split into a rotation, a translation and a second rotation,
+// each perturbed with a std dev that grows with the rotation (alpha1, alpha4) and translation
+// (alpha2, alpha3) involved.
+#[derive(Copy, Clone, Debug)]
+pub struct OdometryMotionModel {
+    pub alpha1: f32, // rotation noise from rotation
+    pub alpha2: f32, // rotation noise from translation
+    pub alpha3: f32, // translation noise from translation
+    pub alpha4: f32, // translation noise from rotation
+}
+
+impl Default for OdometryMotionModel {
+    // values used by the ROS amcl package
+    fn default() -> Self {
+        OdometryMotionModel {
+            alpha1: 0.2,
+            alpha2: 0.2,
+            alpha3: 0.2,
+            alpha4: 0.2,
+        }
+    }
+}
+
+impl OdometryMotionModel {
+    // a possible pose after moving from pose by the motion odometry saw between its two readings
+    pub fn sample<R: Rng>(&self, pose: Pose, odom_prev: Pose, odom_now: Pose, rng: &mut R) -> Pose {
+        let dx = odom_now.x - odom_prev.x;
+        let dy = odom_now.y - odom_prev.y;
+        let trans = (dx * dx + dy * dy).sqrt();
+        // the heading of very short motions is meaningless, treat them as a turn on the spot
+        let rot1 = if trans < 0.01 {
+            0.
+        } else {
+            normalise_angle(dy.atan2(dx) - odom_prev.theta)
+        };
+        let rot2 = normalise_angle(odom_now.theta - odom_prev.theta - rot1);
+
+        // driving backwards looks like turning around, then forwards, then back again
+        let rot1_noise = rot1.abs().min((PI - rot1.abs()).abs());
+        let rot2_noise = rot2.abs().min((PI - rot2.abs()).abs());
+
+        let rot1_hat = rot1
+            - sample_normal(
+                self.alpha1 * rot1_noise.powi(2) + self.alpha2 * trans.powi(2),
+                rng,
+            );
+        let trans_hat = trans
+            - sample_normal(
+                self.alpha3 * trans.powi(2)
+                    + self.alpha4 * (rot1_noise.powi(2) + rot2_noise.powi(2)),
+                rng,
+            );
+        let rot2_hat = rot2
+            - sample_normal(
+                self.alpha1 * rot2_noise.powi(2) + self.alpha2 * trans.powi(2),
+                rng,
+            );
+
+        Pose {
+            x: pose.x + trans_hat * (pose.theta + rot1_hat).cos(),
+            y: pose.y + trans_hat * (pose.theta + rot1_hat).sin(),
+            theta: normalise_angle(pose.theta + rot1_hat + rot2_hat),
+        }
+    }
+}
+
+// linear and angular velocity of the base from the rim speeds of its wheels, or equally the
+// distance and angle it covers from the distances the wheels roll
+pub fn body_velocity(left: f32, right: f32, track_width: f32) -> (f32, f32) {
+    let linear_vel = (right + left) / 2.0;
+    let angular_vel = (right - left) / track_width;
+    (linear_vel, angular_vel)
+}
+
+// wheel rim speeds that give the base a linear and angular velocity, inverse of body_velocity
+pub fn wheel_velocities(linear_vel: f32, angular_vel: f32, track_width: f32) -> (f32, f32) {
+    let difference = angular_vel * track_width;
+    (linear_vel - difference / 2.0, linear_vel + difference / 2.0)
+}
+
+// pose after driving at constant linear and angular velocity for time_step. The base follows an
+// arc, whose chord points halfway between the start and end headings and has length
+// 2 r sin(dtheta / 2) = distance * sinc(dtheta / 2), which stays exact as the arc straightens out.
+pub fn integrate(pose: Pose, linear_vel: f32, angular_vel: f32, time_step: f32) -> Pose {
+    let half_turn = angular_vel * time_step / 2.;
+    let chord = linear_vel * time_step * sinc(half_turn as f64).0 as f32;
+    let heading = pose.theta + half_turn;
+    Pose {
+        x: pose.x + chord * heading.cos(),
+        y: pose.y + chord * heading.sin(),
+        theta: normalise_angle(pose.theta + 2. * half_turn),
+    }
+}
+
+// sin(x) / x and its derivative, using their series near 0
+pub(crate) fn sinc(x: f64) -> (f64, f64) {
+    if x.abs() < 1e-4 {
+        (1. - x * x / 6., -x / 3.)
+    } else {
+        (x.sin() / x, (x * x.cos() - x.sin()) / (x * x))
+    }
+}
+
+// zero mean normal sample with the given variance
+fn sample_normal<R: Rng>(variance: f32, rng: &mut R) -> f32 {
+    if variance > 0. {
+        Normal::new(0., variance.sqrt()).unwrap().sample(rng)
+    } else {
+        0.
+    }
+}
+
+// wrap an angle into (-pi, pi]
+pub fn normalise_angle(angle: f32) -> f32 {
+    angle.sin().atan2(angle.cos())
+}
+
 #[derive(Debug, Copy, Clone)]
 struct WheelVel {
     pub left: f32,
@@ -27,8 +292,12 @@ pub struct RobotState {
 }
 
 pub struct Robot {
-    pub state: RobotState,
-    wheel_base: f32,
+    pub state: RobotState,  // ground truth
+    pub odometry: Odometry, // what the robot believes from its wheel encoders
+    limits: VelocityLimits,
+    pub kinematics: DiffDriveKinematics,
+    pub footprint: Footprint,
+    pub collision_response: CollisionResponse, // what happens on contact when stepping with step_in
     command: RobotCommand,
 }
 
@@ -36,9 +305,18 @@ const TURN_VEL: f32 = 0.1;
 const LINEAR_VEL: f32 = 0.1;
 
 impl Robot {
-    pub fn new(wheel_base: f32) -> Self {
+    // robot with perfect odometry
+    pub fn new(kinematics: DiffDriveKinematics) -> Self {
+        Robot::with_odometry(kinematics, OdometryConfig::default())
+    }
+
+    pub fn with_odometry(kinematics: DiffDriveKinematics, odometry: OdometryConfig) -> Self {
         Robot {
-            wheel_base,
+            odometry: Odometry::new(odometry, Pose::default()),
+            limits: VelocityLimits::default(),
+            kinematics,
+            footprint: Footprint::default(),
+            collision_response: CollisionResponse::Stop,
             state: RobotState {
                 pose: Pose {
                     x: 0.,
@@ -53,49 +331,304 @@ impl Robot {
             command: RobotCommand::Stop,
         }
     }
+    pub fn limits(&self) -> VelocityLimits {
+        self.limits
+    }
+
+    // the limits are left unchanged if any of them is negative or NaN
+    pub fn set_limits(&mut self, limits: VelocityLimits) -> Result<(), InvalidVelocityLimit> {
+        limits.validate()?;
+        self.limits = limits;
+        Ok(())
+    }
+
     pub fn set_command(&mut self, command: RobotCommand) {
         self.command = command;
     }
+
+    pub fn set_twist(&mut self, linear: f32, angular: f32) {
+        self.command = RobotCommand::Twist(Twist { linear, angular });
+    }
+
+    pub fn set_wheel_velocities(&mut self, left: f32, right: f32) {
+        self.command = RobotCommand::WheelVelocity { left, right };
+    }
+
+    // current (left, right) wheel velocities in rad/s
+    pub fn wheel_velocity(&self) -> (f32, f32) {
+        (
+            self.state.wheel_velocity.left,
+            self.state.wheel_velocity.right,
+        )
+    }
+
+    // current linear (m/s) and angular (rad/s) velocity of the base
+    pub fn velocity(&self) -> (f32, f32) {
+        let wheel_vel = self.state.wheel_velocity;
+        self.kinematics
+            .body_velocity(wheel_vel.left, wheel_vel.right)
+    }
+
     pub fn step(&mut self, time_step: f32) {
-        let wheel_vel = match self.command {
-            RobotCommand::Forward => WheelVel {
-                left: LINEAR_VEL,
-                right: LINEAR_VEL,
-            },
-            RobotCommand::Back => WheelVel {
-                left: -LINEAR_VEL,
-                right: -LINEAR_VEL,
-            },
-            RobotCommand::TurnLeft => WheelVel {
-                left: -TURN_VEL,
-                right: TURN_VEL,
-            },
-            RobotCommand::TurnRight => WheelVel {
-                left: TURN_VEL,
-                right: -TURN_VEL,
-            },
-            RobotCommand::Stop => WheelVel {
-                left: 0.,
-                right: 0.,
-            },
-        };
+        let wheel_vel = self.limited_wheel_velocity(time_step);
         self.state.wheel_velocity = wheel_vel;
 
-        let linear_vel = (wheel_vel.right + wheel_vel.left) / 2.0;
-        let angular_vel = (wheel_vel.right - wheel_vel.left) / 2.0;
+        let (linear_vel, angular_vel) = self.velocity();
+        self.state.pose = integrate(self.state.pose, linear_vel, angular_vel, time_step);
+        self.update_odometry(wheel_vel, time_step);
+    }
 
-        let x_dot = linear_vel * self.state.pose.theta.cos();
-        let y_dot = linear_vel * self.state.pose.theta.sin();
-        let theta_dot = angular_vel / self.wheel_base;
+    // dead reckon from the wheels turning at wheel_vel for time_step
+    fn update_odometry(&mut self, wheel_vel: WheelVel, time_step: f32) {
+        let radius = self.kinematics.wheel_radius;
+        self.odometry.update(
+            wheel_vel.left * radius * time_step,
+            wheel_vel.right * radius * time_step,
+            self.kinematics.track_width,
+        );
+    }
 
-        let new_state = RobotState {
-            pose: Pose {
-                x: self.state.pose.x + x_dot * time_step,
-                y: self.state.pose.y + y_dot * time_step,
-                theta: self.state.pose.theta + theta_dot * time_step,
-            },
-            wheel_velocity: wheel_vel,
+    // the commanded velocity after applying the velocity, acceleration and wheel speed limits
+    fn limited_wheel_velocity(&self, time_step: f32) -> WheelVel {
+        // the keyboard commands give wheel rim speeds in m/s
+        let track_width = self.kinematics.track_width;
+        let (target_linear, target_angular) = match self.command {
+            RobotCommand::Forward => body_velocity(LINEAR_VEL, LINEAR_VEL, track_width),
+            RobotCommand::Back => body_velocity(-LINEAR_VEL, -LINEAR_VEL, track_width),
+            RobotCommand::TurnLeft => body_velocity(-TURN_VEL, TURN_VEL, track_width),
+            RobotCommand::TurnRight => body_velocity(TURN_VEL, -TURN_VEL, track_width),
+            RobotCommand::Stop => (0., 0.),
+            RobotCommand::Twist(twist) => (twist.linear, twist.angular),
+            RobotCommand::WheelVelocity { left, right } => {
+                self.kinematics.body_velocity(left, right)
+            }
+        };
+
+        let limits = self.limits;
+        let (linear, angular) = self.velocity();
+        let linear_change =
+            target_linear.clamp(-limits.max_linear_vel, limits.max_linear_vel) - linear;
+        let angular_change =
+            target_angular.clamp(-limits.max_angular_vel, limits.max_angular_vel) - angular;
+        // take the same fraction of both changes so the velocity heads straight for the target,
+        // rather than reaching one component first and bending the path while it accelerates
+        let fraction = ramp_fraction(linear_change, limits.max_linear_accel * time_step).min(
+            ramp_fraction(angular_change, limits.max_angular_accel * time_step),
+        );
+        let linear = linear + fraction * linear_change;
+        let angular = angular + fraction * angular_change;
+
+        let (mut left, mut right) = self.kinematics.wheel_velocities(linear, angular);
+        let fastest = left.abs().max(right.abs());
+        if fastest > limits.max_wheel_vel {
+            let scale = limits.max_wheel_vel / fastest;
+            left *= scale;
+            right *= scale;
+        }
+        WheelVel { left, right }
+    }
+}
+
+// fraction of change that can be made without exceeding max_change
+fn ramp_fraction(change: f32, max_change: f32) -> f32 {
+    if change.abs() > max_change {
+        max_change / change.abs()
+    } else {
+        1.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn constant_twist_drives_round_a_circle() {
+        let (linear_vel, angular_vel) = (0.4, 0.8);
+        let radius = linear_vel / angular_vel;
+        let start = Pose {
+            x: 1.,
+            y: -2.,
+            theta: 0.6,
         };
-        self.state = new_state;
+        // centre of the turn, to the left of the start heading
+        let centre_x = start.x - radius * start.theta.sin();
+        let centre_y = start.y + radius * start.theta.cos();
+
+        let steps = 1000;
+        let period = 2. * PI / angular_vel;
+        let mut pose = start;
+        for _ in 0..steps {
+            pose = integrate(pose, linear_vel, angular_vel, period / steps as f32);
+            let distance = ((pose.x - centre_x).powi(2) + (pose.y - centre_y).powi(2)).sqrt();
+            assert_abs_diff_eq!(distance, radius, epsilon = 1e-4);
+        }
+        assert_abs_diff_eq!(pose.x, start.x, epsilon = 1e-3);
+        assert_abs_diff_eq!(pose.y, start.y, epsilon = 1e-3);
+        assert_abs_diff_eq!(
+            normalise_angle(pose.theta - start.theta),
+            0.,
+            epsilon = 1e-3
+        );
+
+        // a single step lands on the same circle however long it is
+        let half_turn = integrate(start, linear_vel, angular_vel, period / 2.);
+        assert_abs_diff_eq!(half_turn.x, 2. * centre_x - start.x, epsilon = 1e-5);
+        assert_abs_diff_eq!(half_turn.y, 2. * centre_y - start.y, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn vanishing_turn_rate_drives_straight() {
+        let start = Pose {
+            x: 0.5,
+            y: 0.25,
+            theta: -1.2,
+        };
+        for &angular_vel in [0., 1e-9, -1e-6].iter() {
+            let pose = integrate(start, 0.3, angular_vel, 10.);
+            assert_abs_diff_eq!(pose.x, start.x + 3. * start.theta.cos(), epsilon = 1e-4);
+            assert_abs_diff_eq!(pose.y, start.y + 3. * start.theta.sin(), epsilon = 1e-4);
+            assert_abs_diff_eq!(pose.theta, start.theta, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn wheel_velocities_round_trip() {
+        let kinematics = DiffDriveKinematics::default();
+        for &(linear_vel, angular_vel) in [(0.5, 0.), (0., 2.), (-0.3, 1.1), (0.2, -0.7)].iter() {
+            let (left, right) = kinematics.wheel_velocities(linear_vel, angular_vel);
+            let (linear, angular) = kinematics.body_velocity(left, right);
+            assert_abs_diff_eq!(linear, linear_vel, epsilon = 1e-6);
+            assert_abs_diff_eq!(angular, angular_vel, epsilon = 1e-6);
+        }
+    }
+
+    // robot at rest under a twist command, with the default kinematics and the given limits
+    fn twist_robot(linear: f32, angular: f32, limits: VelocityLimits) -> Robot {
+        let mut robot = Robot::new(DiffDriveKinematics::default());
+        robot.set_limits(limits).unwrap();
+        robot.set_twist(linear, angular);
+        robot
+    }
+
+    #[test]
+    fn velocity_is_clamped_to_the_limits() {
+        let limits = VelocityLimits::default();
+        let mut robot = twist_robot(5., 0., limits);
+        for _ in 0..20 {
+            robot.step(0.1);
+        }
+        assert_abs_diff_eq!(robot.velocity().0, limits.max_linear_vel, epsilon = 1e-5);
+
+        let mut robot = twist_robot(0., -10., limits);
+        for _ in 0..20 {
+            robot.step(0.1);
+        }
+        assert_abs_diff_eq!(robot.velocity().1, -limits.max_angular_vel, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn acceleration_is_capped_each_step() {
+        let limits = VelocityLimits::default();
+        let time_step = 0.1;
+        let mut robot = twist_robot(0.5, 0., limits);
+        let mut previous = 0.;
+        for _ in 0..4 {
+            robot.step(time_step);
+            let linear = robot.velocity().0;
+            assert_abs_diff_eq!(
+                linear - previous,
+                limits.max_linear_accel * time_step,
+                epsilon = 1e-5
+            );
+            previous = linear;
+        }
+
+        let mut robot = twist_robot(0., 1., limits);
+        robot.step(time_step);
+        assert_abs_diff_eq!(
+            robot.velocity().1,
+            limits.max_angular_accel * time_step,
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn accelerating_keeps_the_commanded_curvature() {
+        // the linear change needs more steps than the angular one, so it sets the pace of both
+        let (target_linear, target_angular) = (0.5, 1.);
+        let mut robot = twist_robot(target_linear, target_angular, VelocityLimits::default());
+        for _ in 0..5 {
+            robot.step(0.1);
+            let (linear, angular) = robot.velocity();
+            assert_abs_diff_eq!(
+                angular / linear,
+                target_angular / target_linear,
+                epsilon = 1e-4
+            );
+        }
+        assert_abs_diff_eq!(robot.velocity().0, target_linear, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn wheel_speed_scaling_keeps_curvature() {
+        // the default kinematics need 16 rad/s on the outer wheel for this twist
+        let (target_linear, target_angular) = (0.5, 2.);
+        let limits = VelocityLimits {
+            max_linear_accel: 100.,
+            max_angular_accel: 100.,
+            max_wheel_vel: 10.,
+            ..VelocityLimits::default()
+        };
+        let mut robot = twist_robot(target_linear, target_angular, limits);
+        robot.step(0.1);
+
+        let (left, right) = robot.wheel_velocity();
+        assert_abs_diff_eq!(left.abs().max(right.abs()), 10., epsilon = 1e-4);
+        let (linear, angular) = robot.velocity();
+        assert!(linear < target_linear);
+        assert_abs_diff_eq!(
+            angular / linear,
+            target_angular / target_linear,
+            epsilon = 1e-4
+        );
+    }
+
+    #[test]
+    fn negative_or_nan_limits_are_rejected() {
+        let mut robot = Robot::new(DiffDriveKinematics::default());
+        let negative = VelocityLimits {
+            max_linear_accel: -1.,
+            ..VelocityLimits::default()
+        };
+        assert_eq!(
+            robot.set_limits(negative),
+            Err(InvalidVelocityLimit {
+                name: "max_linear_accel",
+                value: -1.
+            })
+        );
+        let nan = VelocityLimits {
+            max_wheel_vel: f32::NAN,
+            ..VelocityLimits::default()
+        };
+        assert_eq!(robot.set_limits(nan).unwrap_err().name, "max_wheel_vel");
+        // the rejected limits were not applied
+        assert_abs_diff_eq!(robot.limits().max_linear_accel, 1.);
+        assert!(robot.limits().max_wheel_vel.is_finite());
+        assert!(robot.set_limits(VelocityLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn sinc_gives_value_and_derivative() {
+        for &x in [5e-5, -2e-3, 0.5, -2.].iter() {
+            let (value, derivative) = sinc(x);
+            assert_abs_diff_eq!(value, x.sin() / x, epsilon = 1e-12);
+            let step = 1e-6;
+            let numerical = (sinc(x + step).0 - sinc(x - step).0) / (2. * step);
+            assert_abs_diff_eq!(derivative, numerical, epsilon = 1e-6);
+        }
+        assert_eq!(sinc(0.), (1., 0.));
     }
 }
@@ -0,0 +1,570 @@
+// Contact between the robot's footprint and the environment. Robot::step_in sweeps the footprint
+// along each step's motion and stops the robot where it first touches the map or a dynamic
+// object, or slides it along the obstacle, reporting a CollisionEvent either way.
+use super::{integrate, normalise_angle, Pose, Robot, WheelVel};
+use crate::lidar::{Environment, PixelCoord};
+use std::f32::consts::PI;
+
+// halvings of the step that locate the point of contact
+const BISECTIONS: usize = 8;
+// rounding allowed when comparing how deep the footprint is in an obstacle before and after a move
+const PENETRATION_TOLERANCE: f32 = 1e-4;
+
+// Outline of the robot base about its pose
+#[derive(Clone, Debug)]
+pub enum Footprint {
+    Circle { radius: f32 },
+    // convex, vertices in the robot frame (x forwards) in order around the outline
+    Polygon(Vec<[f32; 2]>),
+}
+
+impl Default for Footprint {
+    // round base just clearing the default 0.3 m track width
+    fn default() -> Self {
+        Footprint::Circle { radius: 0.2 }
+    }
+}
+
+impl Footprint {
+    // rectangle centred on the wheel axle
+    pub fn rectangle(length: f32, width: f32) -> Self {
+        let (x, y) = (length / 2., width / 2.);
+        Footprint::Polygon(vec![[x, y], [-x, y], [-x, -y], [x, -y]])
+    }
+
+    // radius of a circle about the robot centre enclosing the footprint
+    pub fn bounding_radius(&self) -> f32 {
+        match self {
+            Footprint::Circle { radius } => *radius,
+            Footprint::Polygon(vertices) => vertices
+                .iter()
+                .map(|[x, y]| (x * x + y * y).sqrt())
+                .fold(0., f32::max),
+        }
+    }
+
+    // distance from a point in the robot frame to the footprint, zero inside it
+    pub fn distance(&self, x: f32, y: f32) -> f32 {
+        self.signed_distance(x, y).max(0.)
+    }
+
+    // distance from a point in the robot frame to the outline, negative inside the footprint
+    fn signed_distance(&self, x: f32, y: f32) -> f32 {
+        match self {
+            Footprint::Circle { radius } => (x * x + y * y).sqrt() - radius,
+            Footprint::Polygon(vertices) => {
+                let edges = vertices.iter().zip(vertices.iter().cycle().skip(1));
+                // a point inside a convex polygon is on the same side of every edge
+                let (mut left_of_all, mut right_of_all) = (true, true);
+                let mut nearest = f32::INFINITY;
+                for ([x0, y0], [x1, y1]) in edges {
+                    let (edge_x, edge_y) = (x1 - x0, y1 - y0);
+                    let (to_x, to_y) = (x - x0, y - y0);
+                    let length_sq = edge_x * edge_x + edge_y * edge_y;
+                    let t = if length_sq > 0. {
+                        ((to_x * edge_x + to_y * edge_y) / length_sq).clamp(0., 1.)
+                    } else {
+                        0.
+                    };
+                    let (dx, dy) = (to_x - t * edge_x, to_y - t * edge_y);
+                    nearest = nearest.min((dx * dx + dy * dy).sqrt());
+                    let cross = edge_x * to_y - edge_y * to_x;
+                    left_of_all &= cross >= 0.;
+                    right_of_all &= cross <= 0.;
+                }
+                if left_of_all || right_of_all {
+                    -nearest
+                } else {
+                    nearest
+                }
+            }
+        }
+    }
+
+    // how far a world point lies inside the footprint at pose, negative outside it
+    fn penetration(&self, pose: Pose, point: [f32; 2]) -> f32 {
+        let local = pose.inverse().compose(Pose {
+            x: point[0],
+            y: point[1],
+            theta: 0.,
+        });
+        -self.signed_distance(local.x, local.y)
+    }
+
+    // points at most spacing apart around the outline, in the robot frame
+    fn outline(&self, spacing: f32) -> Vec<[f32; 2]> {
+        match self {
+            Footprint::Circle { radius } => {
+                let count = (2. * PI * radius / spacing).ceil().max(3.) as usize;
+                (0..count)
+                    .map(|i| {
+                        let angle = 2. * PI * i as f32 / count as f32;
+                        [radius * angle.cos(), radius * angle.sin()]
+                    })
+                    .collect()
+            }
+            Footprint::Polygon(vertices) => {
+                let edges = vertices.iter().zip(vertices.iter().cycle().skip(1));
+                let mut points = vec![];
+                for ([x0, y0], [x1, y1]) in edges {
+                    let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+                    let count = (length / spacing).ceil().max(1.) as usize;
+                    for i in 0..count {
+                        let t = i as f32 / count as f32;
+                        points.push([x0 + t * (x1 - x0), y0 + t * (y1 - y0)]);
+                    }
+                }
+                points
+            }
+        }
+    }
+
+    // The contact nearest the robot centre when the footprint is at pose, if it touches an
+    // occupied cell of the static grid, the edge of the map or a dynamic object
+    pub fn contact(&self, pose: Pose, environment: &Environment) -> Option<Contact> {
+        let to_local = pose.inverse();
+        let local = |x: f32, y: f32| to_local.compose(Pose { x, y, theta: 0. });
+        let geometry = environment.geometry;
+        let resolution = geometry.resolution;
+        let reach = self.bounding_radius();
+        let mut contacts = vec![];
+
+        // occupied cells whose centre lies within half a cell of the footprint
+        let (centre_x, centre_y) = geometry.world_to_continuous(pose.x, pose.y);
+        let cells = (reach / resolution).ceil() as isize + 1;
+        let (centre_x, centre_y) = (centre_x.floor() as isize, centre_y.floor() as isize);
+        for row in (centre_y - cells).max(0)..=centre_y + cells {
+            for col in (centre_x - cells).max(0)..=centre_x + cells {
+                let in_bounds = (row as usize) < geometry.dimensions.height
+                    && (col as usize) < geometry.dimensions.width;
+                if !in_bounds || !environment.grid[[row as usize, col as usize]] {
+                    continue;
+                }
+                let coord = PixelCoord {
+                    x: col as usize,
+                    y: row as usize,
+                };
+                let (x, y) = geometry.grid_to_world(coord);
+                let point = local(x, y);
+                if self.distance(point.x, point.y) <= resolution / 2. {
+                    contacts.push(Contact {
+                        point: [x, y],
+                        obstacle: Obstacle::Map,
+                    });
+                }
+            }
+        }
+
+        // the outline leaving the map, or touching a dynamic object
+        let outline: Vec<Pose> = self
+            .outline(resolution)
+            .iter()
+            .map(|[x, y]| {
+                pose.compose(Pose {
+                    x: *x,
+                    y: *y,
+                    theta: 0.,
+                })
+            })
+            .collect();
+        for point in &outline {
+            if environment.world_to_grid(point.x, point.y).is_none() {
+                contacts.push(Contact {
+                    point: [point.x, point.y],
+                    obstacle: Obstacle::Map,
+                });
+            }
+        }
+        for (index, object) in environment.dynamic_objects.iter().enumerate() {
+            let dx = object.pose.x - pose.x;
+            let dy = object.pose.y - pose.y;
+            if (dx * dx + dy * dy).sqrt() > reach + object.bounding_radius() {
+                continue;
+            }
+            let obstacle = Obstacle::Dynamic(index);
+            for point in outline.iter().filter(|p| object.contains(p.x, p.y)) {
+                contacts.push(Contact {
+                    point: [point.x, point.y],
+                    obstacle,
+                });
+            }
+            // objects small enough to fit between the outline points
+            let centre = local(object.pose.x, object.pose.y);
+            if self.distance(centre.x, centre.y) == 0. {
+                contacts.push(Contact {
+                    point: [object.pose.x, object.pose.y],
+                    obstacle,
+                });
+            }
+        }
+
+        let distance = |contact: &Contact| {
+            (contact.point[0] - pose.x).powi(2) + (contact.point[1] - pose.y).powi(2)
+        };
+        contacts
+            .into_iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+    }
+
+    // Furthest fraction of a motion, given as the pose at each fraction from 0 to 1, that the
+    // footprint makes without contact, and the contact that stops it short. Poses are checked
+    // often enough that no point of the footprint moves more than half a cell between them.
+    fn sweep(
+        &self,
+        path: impl Fn(f32) -> Pose,
+        distance: f32,
+        rotation: f32,
+        environment: &Environment,
+    ) -> (f32, Option<Contact>) {
+        let travel = distance.abs() + rotation.abs() * self.bounding_radius();
+        let steps = (2. * travel / environment.geometry.resolution)
+            .ceil()
+            .max(1.) as usize;
+        let mut clear = 0.;
+        for i in 1..=steps {
+            let fraction = i as f32 / steps as f32;
+            if let Some(mut contact) = self.contact(path(fraction), environment) {
+                let mut blocked = fraction;
+                for _ in 0..BISECTIONS {
+                    let middle = (clear + blocked) / 2.;
+                    match self.contact(path(middle), environment) {
+                        Some(closer) => {
+                            blocked = middle;
+                            contact = closer;
+                        }
+                        None => clear = middle,
+                    }
+                }
+                return (clear, Some(contact));
+            }
+            clear = fraction;
+        }
+        (1., None)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Obstacle {
+    Map,
+    Dynamic(usize), // index into Environment::dynamic_objects
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Contact {
+    pub point: [f32; 2], // world position of the obstacle where it touches the footprint
+    pub obstacle: Obstacle,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CollisionResponse {
+    Stop,  // halt the wheels at the point of contact, as a bumper switch would
+    Slide, // keep driving, with the base sliding along the obstacle and the wheels slipping
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CollisionEvent {
+    pub contact: Contact,
+    pub pose: Pose,      // where the robot touched the obstacle
+    pub linear_vel: f32, // m/s at the time of contact
+}
+
+impl Robot {
+    // Step as Robot::step, but moving only as far as the footprint stays clear of the environment.
+    // A robot already overlapping an obstacle, say because a dynamic object walked into it, may
+    // still make moves that take it further from the contact without going deeper into any
+    // obstacle, so a polygon turning in place cannot swing a corner further in.
+    pub fn step_in(&mut self, time_step: f32, environment: &Environment) -> Option<CollisionEvent> {
+        let wheel_vel = self.limited_wheel_velocity(time_step);
+        self.state.wheel_velocity = wheel_vel;
+        let (linear_vel, angular_vel) = self.velocity();
+        let start = self.state.pose;
+        let end = integrate(start, linear_vel, angular_vel, time_step);
+
+        let (fraction, contact) = match self.footprint.contact(start, environment) {
+            Some(contact) => {
+                let clearance = |pose: Pose| {
+                    (contact.point[0] - pose.x).powi(2) + (contact.point[1] - pose.y).powi(2)
+                };
+                let depth = self.footprint.penetration(start, contact.point);
+                let deeper = match self.footprint.contact(end, environment) {
+                    Some(next) => {
+                        self.footprint.penetration(end, next.point) > depth + PENETRATION_TOLERANCE
+                    }
+                    None => false,
+                };
+                if clearance(end) >= clearance(start) && !deeper {
+                    (1., None)
+                } else {
+                    (0., Some(contact))
+                }
+            }
+            None => self.footprint.sweep(
+                |fraction| integrate(start, linear_vel, angular_vel, fraction * time_step),
+                linear_vel * time_step,
+                angular_vel * time_step,
+                environment,
+            ),
+        };
+        let contact = match contact {
+            Some(contact) => contact,
+            None => {
+                self.state.pose = end;
+                self.update_odometry(wheel_vel, time_step);
+                return None;
+            }
+        };
+
+        let touching = integrate(start, linear_vel, angular_vel, fraction * time_step);
+        match self.collision_response {
+            CollisionResponse::Stop => {
+                self.state.pose = touching;
+                self.state.wheel_velocity = WheelVel {
+                    left: 0.,
+                    right: 0.,
+                };
+                self.update_odometry(wheel_vel, fraction * time_step);
+            }
+            CollisionResponse::Slide => {
+                // drop the part of the remaining motion heading into the obstacle
+                let normal_x = touching.x - contact.point[0];
+                let normal_y = touching.y - contact.point[1];
+                let length = (normal_x * normal_x + normal_y * normal_y).sqrt().max(1e-6);
+                let (normal_x, normal_y) = (normal_x / length, normal_y / length);
+                let (mut dx, mut dy) = (end.x - touching.x, end.y - touching.y);
+                let into = dx * normal_x + dy * normal_y;
+                if into < 0. {
+                    dx -= into * normal_x;
+                    dy -= into * normal_y;
+                }
+                let slide = Pose {
+                    x: touching.x + dx,
+                    y: touching.y + dy,
+                    theta: end.theta,
+                };
+                let (slid, _) = self.footprint.sweep(
+                    |fraction| touching.interpolate(slide, fraction),
+                    (dx * dx + dy * dy).sqrt(),
+                    normalise_angle(slide.theta - touching.theta),
+                    environment,
+                );
+                self.state.pose = touching.interpolate(slide, slid);
+                self.state.pose.theta = normalise_angle(self.state.pose.theta);
+                // the wheels turned the whole step, so odometry believes the robot went all the way
+                self.update_odometry(wheel_vel, time_step);
+            }
+        }
+        Some(CollisionEvent {
+            contact,
+            pose: touching,
+            linear_vel,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{DiffDriveKinematics, VelocityLimits};
+    use super::*;
+    use crate::lidar::dynamic::{DynamicObject, Shape, Trajectory};
+    use approx::assert_abs_diff_eq;
+    use ndarray::Array2;
+
+    const RESOLUTION: f32 = 0.05;
+    // x of the face of the wall across the room
+    const WALL_X: f32 = 1.;
+
+    // 4 x 4 m room with 5 cm walls, and a 5 cm thick wall across it at WALL_X
+    fn room() -> Environment {
+        let mut grid = Array2::from_elem((80, 80), false);
+        for ((row, col), cell) in grid.indexed_iter_mut() {
+            *cell = !(1..79).contains(&row) || !(1..79).contains(&col) || col == 60;
+        }
+        Environment::centred(grid, RESOLUTION)
+    }
+
+    // robot that reaches any commanded velocity in one step
+    fn robot(footprint: Footprint, pose: Pose, response: CollisionResponse) -> Robot {
+        let mut robot = Robot::new(DiffDriveKinematics::default());
+        robot
+            .set_limits(VelocityLimits {
+                max_linear_accel: 100.,
+                max_angular_accel: 100.,
+                ..VelocityLimits::default()
+            })
+            .unwrap();
+        robot.footprint = footprint;
+        robot.collision_response = response;
+        robot.state.pose = pose;
+        robot
+    }
+
+    fn pose(x: f32, y: f32, theta: f32) -> Pose {
+        Pose { x, y, theta }
+    }
+
+    #[test]
+    fn circle_distance_is_measured_from_the_rim() {
+        let circle = Footprint::Circle { radius: 0.2 };
+        assert_abs_diff_eq!(circle.distance(0.5, 0.), 0.3, epsilon = 1e-6);
+        assert_abs_diff_eq!(circle.distance(-0.3, 0.4), 0.3, epsilon = 1e-6);
+        assert_eq!(circle.distance(0.1, -0.1), 0.);
+        assert_abs_diff_eq!(circle.bounding_radius(), 0.2);
+    }
+
+    #[test]
+    fn polygon_distance_is_measured_from_the_nearest_edge_or_vertex() {
+        let rectangle = Footprint::rectangle(0.6, 0.3);
+        // beside an edge and diagonally off a vertex
+        assert_abs_diff_eq!(rectangle.distance(0.5, 0.), 0.2, epsilon = 1e-6);
+        assert_abs_diff_eq!(rectangle.distance(0., -0.35), 0.2, epsilon = 1e-6);
+        assert_abs_diff_eq!(
+            rectangle.distance(0.4, 0.25),
+            0.02f32.sqrt(),
+            epsilon = 1e-6
+        );
+        assert_eq!(rectangle.distance(0.29, 0.14), 0.);
+        assert_eq!(rectangle.distance(0., 0.), 0.);
+        assert_abs_diff_eq!(
+            rectangle.bounding_radius(),
+            0.1125f32.sqrt(),
+            epsilon = 1e-6
+        );
+
+        // the same outline with its vertices in the opposite order
+        let clockwise =
+            Footprint::Polygon(vec![[0.3, 0.15], [0.3, -0.15], [-0.3, -0.15], [-0.3, 0.15]]);
+        assert_abs_diff_eq!(clockwise.distance(0.5, 0.), 0.2, epsilon = 1e-6);
+        assert_eq!(clockwise.distance(0.29, 0.14), 0.);
+    }
+
+    #[test]
+    fn robot_driving_at_a_wall_stops_within_half_a_cell() {
+        let environment = room();
+        let mut robot = robot(
+            Footprint::default(),
+            pose(0., 0.1, 0.),
+            CollisionResponse::Stop,
+        );
+        robot.set_twist(0.5, 0.);
+        let event = (0..40)
+            .find_map(|_| robot.step_in(0.1, &environment))
+            .expect("robot never reached the wall");
+
+        assert_eq!(event.contact.obstacle, Obstacle::Map);
+        let front = robot.state.pose.x + 0.2;
+        assert!(front <= WALL_X + RESOLUTION / 2., "front at {}", front);
+        assert!(WALL_X - front <= RESOLUTION / 2., "front at {}", front);
+        assert_abs_diff_eq!(robot.state.pose.y, 0.1);
+        assert_eq!(robot.wheel_velocity(), (0., 0.));
+        assert_abs_diff_eq!(event.linear_vel, 0.5);
+
+        // the wheels stay stopped against the wall
+        assert!(robot.step_in(0.1, &environment).is_some());
+        assert!(robot.state.pose.x + 0.2 <= WALL_X + RESOLUTION / 2.);
+    }
+
+    #[test]
+    fn sliding_keeps_the_motion_along_the_wall() {
+        let environment = room();
+        let heading = PI / 4.;
+        let mut robot = robot(
+            Footprint::default(),
+            pose(0.5, -0.8, heading),
+            CollisionResponse::Slide,
+        );
+        robot.set_twist(0.5, 0.);
+        (0..40)
+            .find_map(|_| robot.step_in(0.1, &environment))
+            .expect("robot never reached the wall");
+
+        let touching = robot.state.pose;
+        for _ in 0..5 {
+            assert!(robot.step_in(0.1, &environment).is_some());
+        }
+        // the tangential part of 5 steps at 0.5 m/s, less what snagging on the wall's cells costs
+        let along_wall = 5. * 0.1 * 0.5 * heading.sin();
+        let moved = robot.state.pose.y - touching.y;
+        assert!(
+            moved > 0.5 * along_wall && moved <= along_wall + 1e-4,
+            "moved {}",
+            moved
+        );
+        assert!(robot.state.pose.x + 0.2 <= WALL_X + RESOLUTION / 2.);
+        assert_abs_diff_eq!(robot.state.pose.theta, heading, epsilon = 1e-5);
+        // the wheels keep turning, so odometry, which starts at the origin, believes the robot
+        // drove further than it did
+        let odometry = robot.odometry.pose;
+        let (dx, dy) = (robot.state.pose.x - 0.5, robot.state.pose.y + 0.8);
+        assert!(odometry.x.hypot(odometry.y) > dx.hypot(dy) + 0.05);
+    }
+
+    #[test]
+    fn robot_in_contact_may_back_away_but_not_push_further_in() {
+        let environment = room();
+        // the front of the circle is 2 cm into the wall
+        let start = pose(0.82, 0., 0.);
+        let footprint = Footprint::default();
+        assert!(footprint.contact(start, &environment).is_some());
+
+        let mut pushing = robot(footprint.clone(), start, CollisionResponse::Stop);
+        pushing.set_twist(0.5, 0.);
+        assert!(pushing.step_in(0.1, &environment).is_some());
+        assert_abs_diff_eq!(pushing.state.pose.x, start.x);
+
+        let mut backing = robot(footprint, start, CollisionResponse::Stop);
+        backing.set_twist(-0.5, 0.);
+        assert!(backing.step_in(0.1, &environment).is_none());
+        assert_abs_diff_eq!(backing.state.pose.x, start.x - 0.05, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn polygon_in_contact_cannot_turn_a_corner_into_the_wall() {
+        let environment = room();
+        // the front edge of the rectangle, flat to the wall and just touching it
+        let start = pose(WALL_X + 0.01 - 0.3, 0., 0.);
+        let footprint = Footprint::rectangle(0.6, 0.3);
+        assert!(footprint.contact(start, &environment).is_some());
+
+        // turning either way swings a front corner into the wall
+        for &angular in [2., -2.].iter() {
+            let mut turning = robot(footprint.clone(), start, CollisionResponse::Stop);
+            turning.set_twist(0., angular);
+            assert!(turning.step_in(0.2, &environment).is_some());
+            assert_eq!(turning.state.pose.theta, 0.);
+        }
+
+        let mut backing = robot(footprint, start, CollisionResponse::Stop);
+        backing.set_twist(-0.5, 0.);
+        assert!(backing.step_in(0.1, &environment).is_none());
+        assert!(backing.state.pose.x < start.x);
+    }
+
+    #[test]
+    fn robot_stops_at_a_dynamic_object() {
+        let mut environment = room();
+        environment.dynamic_objects.push(DynamicObject::new(
+            Shape::Circle { radius: 0.1 },
+            pose(0.5, 0., 0.),
+            Trajectory::Stationary,
+            0,
+        ));
+        let mut robot = robot(
+            Footprint::default(),
+            pose(-0.5, 0., 0.),
+            CollisionResponse::Stop,
+        );
+        robot.set_twist(0.5, 0.);
+        let event = (0..40)
+            .find_map(|_| robot.step_in(0.1, &environment))
+            .expect("robot never reached the object");
+
+        assert_eq!(event.contact.obstacle, Obstacle::Dynamic(0));
+        // the circles' rims meet, to within the spacing of the outline points
+        let gap = 0.5 - robot.state.pose.x - 0.3;
+        assert!((0. ..=RESOLUTION / 2.).contains(&gap), "gap {}", gap);
+
+        // and the robot may back away from it
+        robot.set_twist(-0.5, 0.);
+        assert!(robot.step_in(0.1, &environment).is_none());
+    }
+}
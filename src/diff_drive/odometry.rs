@@ -0,0 +1,255 @@
+// Dead reckoning from simulated wheel encoders, kept apart from the robot's true pose. Errors come
+// from tick quantisation, wheel slip, wheel radius and track width calibration, and optionally the
+// alpha1-alpha4 odometry motion model on top.
+use super::{body_velocity, integrate, OdometryMotionModel, Pose};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, Normal};
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone, Debug)]
+pub struct WheelEncoderConfig {
+    pub ticks_per_revolution: u32,
+    pub wheel_radius: f32,      // metres, the nominal radius odometry assumes
+    pub left_radius_error: f32, // fraction the true left radius differs from the nominal one
+    pub right_radius_error: f32,
+    pub track_width_error: f32, // fraction the assumed track width differs from the true one
+    pub slip_std_dev: f32,      // fraction of each wheel's travel that it spins or skids
+}
+
+impl Default for WheelEncoderConfig {
+    // perfectly calibrated 1024 tick encoders on 5 cm wheels, only quantisation error
+    fn default() -> Self {
+        WheelEncoderConfig {
+            ticks_per_revolution: 1024,
+            wheel_radius: 0.05,
+            left_radius_error: 0.,
+            right_radius_error: 0.,
+            track_width_error: 0.,
+            slip_std_dev: 0.,
+        }
+    }
+}
+
+// Perfect odometry by default, equal to the true pose
+#[derive(Copy, Clone, Debug, Default)]
+pub struct OdometryConfig {
+    pub encoders: Option<WheelEncoderConfig>, // None measures wheel travel exactly
+    pub motion_noise: Option<OdometryMotionModel>, // applied to each step's odometry increment
+    pub seed: Option<u64>,                    // None seeds from entropy
+}
+
+pub struct Odometry {
+    pub config: OdometryConfig,
+    pub pose: Pose,
+    pub left_ticks: i64,
+    pub right_ticks: i64,
+    left_remainder: f32, // fraction of a tick turned but not yet counted
+    right_remainder: f32,
+    rng: StdRng,
+}
+
+impl Odometry {
+    pub fn new(config: OdometryConfig, initial_pose: Pose) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Odometry {
+            config,
+            pose: initial_pose,
+            left_ticks: 0,
+            right_ticks: 0,
+            left_remainder: 0.,
+            right_remainder: 0.,
+            rng,
+        }
+    }
+
+    // Dead reckon from the distance each wheel truly rolled over the ground this step
+    pub fn update(&mut self, left_travel: f32, right_travel: f32, track_width: f32) {
+        let (left, right, track_width) = match self.config.encoders {
+            Some(encoders) => {
+                let left_ticks =
+                    self.wheel_ticks(&encoders, left_travel, encoders.left_radius_error);
+                let right_ticks =
+                    self.wheel_ticks(&encoders, right_travel, encoders.right_radius_error);
+                let left = count_ticks(&mut self.left_remainder, left_ticks);
+                let right = count_ticks(&mut self.right_remainder, right_ticks);
+                self.left_ticks += left;
+                self.right_ticks += right;
+                let metres_per_tick =
+                    2. * PI * encoders.wheel_radius / encoders.ticks_per_revolution as f32;
+                (
+                    left as f32 * metres_per_tick,
+                    right as f32 * metres_per_tick,
+                    track_width * (1. + encoders.track_width_error),
+                )
+            }
+            None => (left_travel, right_travel, track_width),
+        };
+
+        // travel over a unit time step is a velocity
+        let (linear, angular) = body_velocity(left, right, track_width);
+        let next = integrate(self.pose, linear, angular, 1.);
+        self.pose = match self.config.motion_noise {
+            Some(model) => model.sample(self.pose, self.pose, next, &mut self.rng),
+            None => next,
+        };
+    }
+
+    // ticks worth of rotation, before quantisation, of a wheel that rolled the given distance
+    fn wheel_ticks(
+        &mut self,
+        encoders: &WheelEncoderConfig,
+        travel: f32,
+        radius_error: f32,
+    ) -> f32 {
+        let slip = if encoders.slip_std_dev > 0. {
+            Normal::new(0., encoders.slip_std_dev)
+                .unwrap()
+                .sample(&mut self.rng)
+        } else {
+            0.
+        };
+        let radius = encoders.wheel_radius * (1. + radius_error);
+        travel * (1. + slip) / (2. * PI * radius) * encoders.ticks_per_revolution as f32
+    }
+}
+
+// whole ticks counted, carrying the part of a tick not yet reached over to the next step
+fn count_ticks(remainder: &mut f32, ticks: f32) -> i64 {
+    let total = *remainder + ticks;
+    let whole = total.trunc();
+    *remainder = total - whole;
+    whole as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const TRACK_WIDTH: f32 = 0.3;
+
+    fn encoders() -> OdometryConfig {
+        OdometryConfig {
+            encoders: Some(WheelEncoderConfig::default()),
+            ..OdometryConfig::default()
+        }
+    }
+
+    // metres of travel per tick of the default encoders
+    fn tick() -> f32 {
+        2. * PI * 0.05 / 1024.
+    }
+
+    // drive the same wheel travel through odometry and the exact kinematics
+    fn drive(config: OdometryConfig, steps: &[(f32, f32)]) -> (Odometry, Pose) {
+        let mut odometry = Odometry::new(config, Pose::default());
+        let mut pose = Pose::default();
+        for &(left, right) in steps {
+            odometry.update(left, right, TRACK_WIDTH);
+            let (linear, angular) = body_velocity(left, right, TRACK_WIDTH);
+            pose = integrate(pose, linear, angular, 1.);
+        }
+        (odometry, pose)
+    }
+
+    #[test]
+    fn count_ticks_carries_the_remainder() {
+        let mut remainder = 0.;
+        let counted: Vec<i64> = (0..5).map(|_| count_ticks(&mut remainder, 0.4)).collect();
+        assert_eq!(counted, [0, 0, 1, 0, 1]);
+        assert_abs_diff_eq!(remainder, 0., epsilon = 1e-6);
+        // backwards counts down, keeping a negative remainder
+        assert_eq!(count_ticks(&mut remainder, -0.7), 0);
+        assert_eq!(count_ticks(&mut remainder, -0.7), -1);
+        assert_abs_diff_eq!(remainder, -0.4, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn quantisation_loses_no_distance() {
+        // each step turns the wheels a third of a tick
+        let step = tick() / 3.;
+        let (odometry, pose) = drive(encoders(), &vec![(step, step); 3000]);
+        assert_eq!(odometry.left_ticks, 1000);
+        assert_eq!(odometry.right_ticks, 1000);
+        assert_abs_diff_eq!(odometry.pose.x, pose.x, epsilon = tick());
+        assert_abs_diff_eq!(odometry.pose.y, 0.);
+    }
+
+    #[test]
+    fn default_config_reproduces_the_true_pose() {
+        let steps = [
+            (0.1, 0.1),
+            (0.05, 0.12),
+            (-0.02, 0.03),
+            (0.2, 0.15),
+            (0.1, 0.1),
+        ];
+        let (odometry, pose) = drive(OdometryConfig::default(), &steps);
+        assert_abs_diff_eq!(odometry.pose.x, pose.x, epsilon = 1e-6);
+        assert_abs_diff_eq!(odometry.pose.y, pose.y, epsilon = 1e-6);
+        assert_abs_diff_eq!(odometry.pose.theta, pose.theta, epsilon = 1e-6);
+
+        // perfectly calibrated encoders are only out by the part of a tick not yet counted
+        let (odometry, pose) = drive(encoders(), &steps);
+        assert_abs_diff_eq!(odometry.pose.x, pose.x, epsilon = 2. * tick());
+        assert_abs_diff_eq!(odometry.pose.y, pose.y, epsilon = 2. * tick());
+        assert_abs_diff_eq!(
+            odometry.pose.theta,
+            pose.theta,
+            epsilon = 2. * tick() / TRACK_WIDTH
+        );
+    }
+
+    #[test]
+    fn calibration_errors_scale_distance_and_heading() {
+        // wheels 2% larger than odometry assumes turn fewer ticks, so distance comes out short
+        let config = OdometryConfig {
+            encoders: Some(WheelEncoderConfig {
+                left_radius_error: 0.02,
+                right_radius_error: 0.02,
+                ..WheelEncoderConfig::default()
+            }),
+            ..OdometryConfig::default()
+        };
+        let (odometry, pose) = drive(config, &[(0.1, 0.1); 20]);
+        assert_abs_diff_eq!(pose.x, 2., epsilon = 1e-5);
+        assert_abs_diff_eq!(odometry.pose.x, 2. / 1.02, epsilon = tick());
+
+        // assuming a 5% wider track makes turns on the spot come out 5% smaller
+        let config = OdometryConfig {
+            encoders: Some(WheelEncoderConfig {
+                track_width_error: 0.05,
+                ..WheelEncoderConfig::default()
+            }),
+            ..OdometryConfig::default()
+        };
+        let (odometry, pose) = drive(config, &[(-0.015, 0.015); 10]);
+        assert_abs_diff_eq!(pose.theta, 1., epsilon = 1e-5);
+        assert_abs_diff_eq!(
+            odometry.pose.theta,
+            1. / 1.05,
+            epsilon = 2. * tick() / TRACK_WIDTH
+        );
+        assert_abs_diff_eq!(odometry.pose.x, 0., epsilon = 1e-6);
+
+        // a smaller right wheel makes a straight line curve to the right
+        let config = OdometryConfig {
+            encoders: Some(WheelEncoderConfig {
+                right_radius_error: -0.01,
+                ..WheelEncoderConfig::default()
+            }),
+            ..OdometryConfig::default()
+        };
+        let (odometry, _) = drive(config, &[(0.1, 0.1); 10]);
+        let heading_drift = 1. * (1. / 0.99 - 1.) / TRACK_WIDTH;
+        assert_abs_diff_eq!(
+            odometry.pose.theta,
+            heading_drift,
+            epsilon = 2. * tick() / TRACK_WIDTH
+        );
+    }
+}
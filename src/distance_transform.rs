@@ -0,0 +1,133 @@
+// Exact Euclidean distance transform from P. Felzenszwalb and D. Huttenlocher, "Distance Transforms
+// of Sampled Functions". Each row and then each column is transformed in linear time as the lower
+// envelope of parabolas rooted at the obstacle cells.
+use ndarray::{Array2, Axis};
+
+// stands in for infinity in the input so that parabola intersections stay finite
+const FAR: f64 = 1e20;
+
+// squared distance in cells from each cell to the nearest true cell, infinity if there are none
+pub fn squared_distance_transform(occupied: &Array2<bool>) -> Array2<f64> {
+    let mut distances = occupied.mapv(|o| if o { 0. } else { FAR });
+    let longest = occupied.nrows().max(occupied.ncols());
+    let mut f = vec![0.; longest];
+    let mut v = vec![0; longest];
+    let mut z = vec![0.; longest + 1];
+    for axis in [Axis(0), Axis(1)] {
+        for mut lane in distances.lanes_mut(axis) {
+            let n = lane.len();
+            for (fq, value) in f.iter_mut().zip(lane.iter()) {
+                *fq = *value;
+            }
+            transform_1d(&f[..n], &mut v, &mut z, lane.iter_mut());
+        }
+    }
+    distances.mapv_inplace(|d| if d >= FAR { f64::INFINITY } else { d });
+    distances
+}
+
+// distance from each cell centre to the nearest true cell centre, in metres
+pub fn distance_transform(occupied: &Array2<bool>, resolution: f32) -> Array2<f32> {
+    squared_distance_transform(occupied).mapv(|d| d.sqrt() as f32 * resolution)
+}
+
+// writes min over p of (q - p)^2 + f(p) for each q, v and z are scratch space for the parabola
+// roots and the boundaries between them
+fn transform_1d<'a>(
+    f: &[f64],
+    v: &mut [usize],
+    z: &mut [f64],
+    output: impl Iterator<Item = &'a mut f64>,
+) {
+    let n = f.len();
+    if n == 0 {
+        return;
+    }
+    let intersection = |p: usize, q: usize| {
+        let (p_f, q_f) = (p as f64, q as f64);
+        ((f[q] + q_f * q_f) - (f[p] + p_f * p_f)) / (2. * q_f - 2. * p_f)
+    };
+
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = f64::NEG_INFINITY;
+    z[1] = f64::INFINITY;
+    for q in 1..n {
+        let mut s = intersection(v[k], q);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(v[k], q);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, d) in output.enumerate() {
+        while z[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - v[k] as f64;
+        *d = offset * offset + f[v[k]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // distance from each cell to every true cell, keeping the nearest
+    fn brute_force(occupied: &Array2<bool>, resolution: f32) -> Array2<f32> {
+        let obstacles: Vec<(usize, usize)> = occupied
+            .indexed_iter()
+            .filter(|(_, o)| **o)
+            .map(|(cell, _)| cell)
+            .collect();
+        Array2::from_shape_fn(occupied.dim(), |(row, col)| {
+            obstacles
+                .iter()
+                .map(|(o_row, o_col)| {
+                    let dr = row as f32 - *o_row as f32;
+                    let dc = col as f32 - *o_col as f32;
+                    (dr * dr + dc * dc).sqrt() * resolution
+                })
+                .fold(f32::INFINITY, f32::min)
+        })
+    }
+
+    #[test]
+    fn matches_brute_force_on_random_grids() {
+        let mut rng = StdRng::seed_from_u64(0);
+        // (rows, cols, fraction occupied), including single rows and columns
+        let grids = [(7, 11, 0.1), (12, 5, 0.3), (1, 9, 0.2), (8, 1, 0.2)];
+        for &(rows, cols, density) in grids.iter() {
+            let occupied = Array2::from_shape_fn((rows, cols), |_| rng.gen_bool(density));
+            let expected = brute_force(&occupied, 0.05);
+            let actual = distance_transform(&occupied, 0.05);
+            for (a, e) in actual.iter().zip(expected.iter()) {
+                if e.is_infinite() {
+                    assert!(a.is_infinite());
+                } else {
+                    assert_abs_diff_eq!(*a, *e, epsilon = 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_obstacle_and_empty_grid() {
+        let mut occupied = Array2::from_elem((5, 6), false);
+        assert!(distance_transform(&occupied, 1.)
+            .iter()
+            .all(|d| d.is_infinite()));
+        occupied[[0, 5]] = true;
+        let distances = distance_transform(&occupied, 1.);
+        assert_eq!(distances[[0, 5]], 0.);
+        assert_abs_diff_eq!(distances[[4, 0]], (16f32 + 25.).sqrt());
+    }
+}
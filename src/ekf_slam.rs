@@ -0,0 +1,385 @@
+// EKF-SLAM with point landmarks, following Probabilistic Robotics by S. Thrun et al. (ch. 10). The
+// state is the robot pose followed by the x, y of each landmark, observations are associated with
+// landmarks by Mahalanobis distance.
+use crate::diff_drive::{integrate, normalise_angle, sinc, Pose};
+use crate::lidar::landmarks::LandmarkObservation;
+use ndarray::prelude::*;
+
+#[derive(Copy, Clone, Debug)]
+pub struct EkfSlamConfig {
+    pub velocity_std_dev: f32,         // m/s
+    pub angular_velocity_std_dev: f32, // rad/s
+    pub range_std_dev: f32,
+    pub bearing_std_dev: f32,
+    pub association_gate: f32, // squared Mahalanobis distance below which an observation matches
+    pub new_landmark_gate: f32, // squared Mahalanobis distance above which it starts a new landmark
+}
+
+impl Default for EkfSlamConfig {
+    // gates at the 95% and 99.9% points of the chi-squared distribution with 2 degrees of freedom
+    fn default() -> Self {
+        EkfSlamConfig {
+            velocity_std_dev: 0.05,
+            angular_velocity_std_dev: 0.05,
+            range_std_dev: 0.05,
+            bearing_std_dev: 0.02,
+            association_gate: 5.99,
+            new_landmark_gate: 13.8,
+        }
+    }
+}
+
+// what update did with each observation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Association {
+    Matched(usize), // landmark index
+    New(usize),
+    Rejected, // between the gates, too ambiguous to use
+}
+
+// an observation of a landmark compared with what the current estimate predicts
+struct Innovation {
+    h: Array2<f64>,     // measurement Jacobian
+    s_inv: Array2<f64>, // inverse innovation covariance
+    distance: f64,      // squared Mahalanobis distance
+    nu: Array1<f64>,    // observed minus predicted range and bearing
+}
+
+pub struct EkfSlam {
+    pub config: EkfSlamConfig,
+    pub mean: Array1<f64>, // x, y, theta, then x, y per landmark
+    pub covariance: Array2<f64>,
+}
+
+impl EkfSlam {
+    // starts certain of the initial pose, which fixes the map frame
+    pub fn new(initial_pose: Pose, config: EkfSlamConfig) -> Self {
+        EkfSlam {
+            config,
+            mean: array![
+                initial_pose.x as f64,
+                initial_pose.y as f64,
+                initial_pose.theta as f64
+            ],
+            covariance: Array2::zeros((3, 3)),
+        }
+    }
+
+    pub fn pose(&self) -> Pose {
+        Pose {
+            x: self.mean[0] as f32,
+            y: self.mean[1] as f32,
+            theta: self.mean[2] as f32,
+        }
+    }
+
+    pub fn landmark_count(&self) -> usize {
+        (self.mean.len() - 3) / 2
+    }
+
+    pub fn landmark(&self, index: usize) -> [f32; 2] {
+        [
+            self.mean[3 + 2 * index] as f32,
+            self.mean[4 + 2 * index] as f32,
+        ]
+    }
+
+    // Move the pose with the diff_drive kinematics. Only the robot rows and columns of the
+    // covariance change, so this is linear in the number of landmarks.
+    pub fn predict(&mut self, linear_vel: f32, angular_vel: f32, time_step: f32) {
+        let pose = self.pose();
+        let new_pose = integrate(pose, linear_vel, angular_vel, time_step);
+        self.mean[0] = new_pose.x as f64;
+        self.mean[1] = new_pose.y as f64;
+        self.mean[2] = normalise_angle(new_pose.theta) as f64;
+
+        // Jacobians of the arc motion with respect to the pose and to the velocities. The base
+        // moves along a chord of length v dt sinc(w dt / 2) at heading theta + w dt / 2.
+        let (v, w, dt) = (linear_vel as f64, angular_vel as f64, time_step as f64);
+        let half_turn = w * dt / 2.;
+        let (sinc, sinc_derivative) = sinc(half_turn);
+        let chord = v * dt * sinc;
+        let (s, c) = (pose.theta as f64 + half_turn).sin_cos();
+        let g = array![[1., 0., -chord * s], [0., 1., chord * c], [0., 0., 1.]];
+        let chord_by_w = v * dt * sinc_derivative * dt / 2.;
+        let v_jac = array![
+            [dt * sinc * c, chord_by_w * c - chord * s * dt / 2.],
+            [dt * sinc * s, chord_by_w * s + chord * c * dt / 2.],
+            [0., dt]
+        ];
+        let m = Array2::from_diag(&array![
+            (self.config.velocity_std_dev as f64).powi(2),
+            (self.config.angular_velocity_std_dev as f64).powi(2)
+        ]);
+
+        let p_rr = self.covariance.slice(s![..3, ..3]).to_owned();
+        let p_rr = g.dot(&p_rr).dot(&g.t()) + v_jac.dot(&m).dot(&v_jac.t());
+        let p_rm = g.dot(&self.covariance.slice(s![..3, 3..]));
+        self.covariance.slice_mut(s![..3, ..3]).assign(&p_rr);
+        self.covariance.slice_mut(s![3.., ..3]).assign(&p_rm.t());
+        self.covariance.slice_mut(s![..3, 3..]).assign(&p_rm);
+    }
+
+    // Fold in range-bearing observations one at a time, each matched to the landmark with the
+    // smallest Mahalanobis distance if it is within the association gate
+    pub fn update(&mut self, observations: &[LandmarkObservation]) -> Vec<Association> {
+        observations
+            .iter()
+            .map(|observation| {
+                let z = array![observation.range as f64, observation.bearing as f64];
+                let nearest = (0..self.landmark_count())
+                    .map(|index| (index, self.innovation(index, &z)))
+                    .min_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance));
+                match nearest {
+                    Some((index, innovation))
+                        if innovation.distance < self.config.association_gate as f64 =>
+                    {
+                        self.correct(&innovation);
+                        Association::Matched(index)
+                    }
+                    Some((_, innovation))
+                        if innovation.distance <= self.config.new_landmark_gate as f64 =>
+                    {
+                        Association::Rejected
+                    }
+                    _ => {
+                        self.add_landmark(&z);
+                        Association::New(self.landmark_count() - 1)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // A zero range or bearing std dev is treated as 1 mm or 1 mrad, so the innovation covariance
+    // stays invertible and a re-observed landmark can still be matched.
+    fn measurement_noise(&self) -> Array2<f64> {
+        Array2::from_diag(&array![
+            (self.config.range_std_dev as f64).max(1e-3).powi(2),
+            (self.config.bearing_std_dev as f64).max(1e-3).powi(2)
+        ])
+    }
+
+    fn innovation(&self, index: usize, z: &Array1<f64>) -> Innovation {
+        let column = 3 + 2 * index;
+        let dx = self.mean[column] - self.mean[0];
+        let dy = self.mean[column + 1] - self.mean[1];
+        let q = (dx * dx + dy * dy).max(1e-12);
+        let range = q.sqrt();
+        let expected = array![range, dy.atan2(dx) - self.mean[2]];
+
+        let mut h = Array2::zeros((2, self.mean.len()));
+        h.slice_mut(s![.., ..3]).assign(&array![
+            [-dx / range, -dy / range, 0.],
+            [dy / q, -dx / q, -1.]
+        ]);
+        h.slice_mut(s![.., column..column + 2])
+            .assign(&array![[dx / range, dy / range], [-dy / q, dx / q]]);
+
+        let s = h.dot(&self.covariance).dot(&h.t()) + self.measurement_noise();
+        let s_inv = inverse_2x2(&s);
+        let mut nu = z - &expected;
+        nu[1] = normalise_angle(nu[1] as f32) as f64;
+        let distance = nu.dot(&s_inv.dot(&nu));
+        Innovation {
+            h,
+            s_inv,
+            distance,
+            nu,
+        }
+    }
+
+    fn correct(&mut self, innovation: &Innovation) {
+        let p_ht = self.covariance.dot(&innovation.h.t());
+        let k = p_ht.dot(&innovation.s_inv);
+        self.mean = &self.mean + &k.dot(&innovation.nu);
+        self.mean[2] = normalise_angle(self.mean[2] as f32) as f64;
+        // P - K S K^T, which is (I - K H) P, kept symmetric against rounding
+        let covariance = &self.covariance - &k.dot(&p_ht.t());
+        self.covariance = (&covariance + &covariance.t()) / 2.;
+    }
+
+    // append a landmark where the observation puts it, with covariance from the pose uncertainty
+    // and the measurement noise
+    fn add_landmark(&mut self, z: &Array1<f64>) {
+        let (range, bearing) = (z[0], z[1]);
+        let angle = self.mean[2] + bearing;
+        let (s, c) = angle.sin_cos();
+        let landmark = array![self.mean[0] + range * c, self.mean[1] + range * s];
+
+        // Jacobians of the landmark position with respect to the pose and to the observation
+        let g_r = array![[1., 0., -range * s], [0., 1., range * c]];
+        let g_z = array![[c, -range * s], [s, range * c]];
+
+        let n = self.mean.len();
+        let p_rx = self.covariance.slice(s![..3, ..]);
+        let p_lx = g_r.dot(&p_rx);
+        let p_ll = g_r.dot(&p_lx.slice(s![.., ..3]).t())
+            + g_z.dot(&self.measurement_noise()).dot(&g_z.t());
+
+        let mut covariance = Array2::zeros((n + 2, n + 2));
+        covariance.slice_mut(s![..n, ..n]).assign(&self.covariance);
+        covariance.slice_mut(s![n.., ..n]).assign(&p_lx);
+        covariance.slice_mut(s![..n, n..]).assign(&p_lx.t());
+        covariance.slice_mut(s![n.., n..]).assign(&p_ll);
+        self.covariance = covariance;
+
+        let mut mean = Array1::zeros(n + 2);
+        mean.slice_mut(s![..n]).assign(&self.mean);
+        mean.slice_mut(s![n..]).assign(&landmark);
+        self.mean = mean;
+    }
+}
+
+fn inverse_2x2(m: &Array2<f64>) -> Array2<f64> {
+    let det = m[[0, 0]] * m[[1, 1]] - m[[0, 1]] * m[[1, 0]];
+    array![[m[[1, 1]], -m[[0, 1]]], [-m[[1, 0]], m[[0, 0]]]] / det
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::landmarks::{LandmarkSensor, LandmarkSensorConfig};
+    use crate::lidar::Environment;
+    use approx::assert_abs_diff_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rand_distr::{Distribution, Normal};
+    use std::collections::HashMap;
+    use std::f32::consts::PI;
+
+    fn observation(range: f32, bearing: f32) -> LandmarkObservation {
+        LandmarkObservation {
+            range,
+            bearing,
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn reobserved_landmark_is_matched() {
+        let mut slam = EkfSlam::new(Pose::default(), EkfSlamConfig::default());
+        assert_eq!(slam.update(&[observation(2., 0.5)]), [Association::New(0)]);
+        // the landmark from 0.2 m further on
+        slam.predict(0.2, 0., 1.);
+        let [x, y] = [2. * 0.5f32.cos() - 0.2, 2. * 0.5f32.sin()];
+        let associations = slam.update(&[observation(x.hypot(y), y.atan2(x))]);
+        assert_eq!(associations, [Association::Matched(0)]);
+        assert_eq!(slam.landmark_count(), 1);
+    }
+
+    #[test]
+    fn zero_noise_reobservation_is_matched() {
+        let config = EkfSlamConfig {
+            velocity_std_dev: 0.,
+            angular_velocity_std_dev: 0.,
+            range_std_dev: 0.,
+            bearing_std_dev: 0.,
+            ..EkfSlamConfig::default()
+        };
+        let mut slam = EkfSlam::new(Pose::default(), config);
+        slam.update(&[observation(1., 0.), observation(2., 1.)]);
+        slam.predict(0.1, 0., 1.);
+        let associations = slam.update(&[observation(0.9, 0.)]);
+        assert_eq!(associations, [Association::Matched(0)]);
+        assert_eq!(slam.landmark_count(), 2);
+    }
+
+    #[test]
+    fn observation_between_the_gates_is_rejected() {
+        // with a certain pose, the innovation covariance of a fresh landmark is twice the
+        // measurement noise, so a range error d has squared Mahalanobis distance d^2 / 0.005
+        let config = EkfSlamConfig::default();
+        for &(range_error, expected) in &[
+            (0.1, Association::Matched(0)),
+            (0.21, Association::Rejected),
+            (0.3, Association::New(1)),
+        ] {
+            let mut slam = EkfSlam::new(Pose::default(), config);
+            slam.update(&[observation(2., 0.5)]);
+            let mean = slam.mean.clone();
+            let associations = slam.update(&[observation(2. + range_error, 0.5)]);
+            assert_eq!(associations, [expected]);
+            if expected == Association::Rejected {
+                assert_eq!(slam.mean, mean);
+                assert_eq!(slam.landmark_count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn estimates_converge_with_a_landmark_sensor() {
+        // 6 x 6 m room with 10 cm walls and posts far enough apart to tell which is which
+        let mut grid = Array2::from_elem((60, 60), false);
+        for ((row, col), cell) in grid.indexed_iter_mut() {
+            *cell = !(1..59).contains(&row) || !(1..59).contains(&col);
+        }
+        let mut environment = Environment::centred(grid, 0.1);
+        environment.landmarks = vec![
+            [-2., 0.],
+            [-1., -1.],
+            [1., -1.],
+            [2., 0.5],
+            [2., 2.5],
+            [0.5, 2.8],
+            [-1.5, 2.7],
+            [0., 1.5],
+        ];
+
+        // the sensor is less noisy than the filter assumes, so no observation is so far out that
+        // it fails the new landmark gate and duplicates its landmark
+        let config = EkfSlamConfig::default();
+        let mut sensor = LandmarkSensor::new(LandmarkSensorConfig {
+            max_range: 3.,
+            range_std_dev: config.range_std_dev / 2.,
+            bearing_std_dev: config.bearing_std_dev / 2.,
+            seed: Some(5),
+            ..LandmarkSensorConfig::default()
+        });
+        let mut slam = EkfSlam::new(Pose::default(), config);
+        let mut true_pose = Pose::default();
+        let mut rng = StdRng::seed_from_u64(6);
+        let velocity_noise = Normal::new(0., config.velocity_std_dev).unwrap();
+        let angular_noise = Normal::new(0., config.angular_velocity_std_dev).unwrap();
+
+        // two laps of a 1.5 m radius circle, the robot drifting off its commanded velocities
+        let (linear_vel, angular_vel, time_step) = (0.3, 0.2, 0.2);
+        let mut landmark_ids = HashMap::new();
+        for _ in 0..(4. * PI / angular_vel / time_step) as usize {
+            let v = linear_vel + velocity_noise.sample(&mut rng);
+            let w = angular_vel + angular_noise.sample(&mut rng);
+            true_pose = integrate(true_pose, v, w, time_step);
+            slam.predict(linear_vel, angular_vel, time_step);
+
+            let observations = sensor.observe(true_pose, &environment);
+            let associations = slam.update(&observations);
+            for (observation, association) in observations.iter().zip(associations) {
+                match association {
+                    Association::New(index) => {
+                        assert!(landmark_ids.insert(index, observation.id).is_none());
+                    }
+                    // always matched to the landmark the observation started
+                    Association::Matched(index) => assert_eq!(landmark_ids[&index], observation.id),
+                    Association::Rejected => (),
+                }
+            }
+        }
+
+        let pose = slam.pose();
+        assert_abs_diff_eq!(pose.x, true_pose.x, epsilon = 0.1);
+        assert_abs_diff_eq!(pose.y, true_pose.y, epsilon = 0.1);
+        assert_abs_diff_eq!(
+            normalise_angle(pose.theta - true_pose.theta),
+            0.,
+            epsilon = 0.05
+        );
+        assert_eq!(slam.landmark_count(), environment.landmarks.len());
+        assert_eq!(slam.landmark_count(), landmark_ids.len());
+        for (index, id) in landmark_ids {
+            let [x, y] = slam.landmark(index);
+            let [true_x, true_y] = environment.landmarks[id];
+            assert_abs_diff_eq!(x, true_x, epsilon = 0.1);
+            assert_abs_diff_eq!(y, true_y, epsilon = 0.1);
+        }
+    }
+}
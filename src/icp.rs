@@ -6,19 +6,21 @@ use kdtree::KdTree;
 use ndarray::prelude::*;
 use ndarray_linalg::{solve::Determinant, svd::*};
 
-// input: two point clouds, reference (dimension mxn) and new (dimension wxn)
+// input: two homogeneous point clouds, reference (dimension mxn) and new (dimension wxn)
 // output: vector of indices of the points in reference that are closest to the points in new, length w
+// works for any dimension, e.g. n = 3 for 2D clouds and n = 4 for 3D clouds
 pub fn nearest_neighbours(reference: &Array2<f64>, new: &Array2<f64>) -> Array1<usize> {
     let dimensions = reference.ncols() - 1;
     let mut kdtree = KdTree::new(dimensions);
-    for i in 0..reference.nrows() {
+    for (i, point) in reference.outer_iter().enumerate() {
         kdtree
-            .add([reference[[i, 0]], reference[[i, 1]]], i)
+            .add(point.slice(s![..dimensions]).to_vec(), i)
             .unwrap();
     }
     let mut result = Vec::new();
-    for i in 0..new.nrows() {
-        let nearest = kdtree.nearest(&[new[[i, 0]], new[[i, 1]]], 1, &squared_euclidean);
+    for point in new.outer_iter() {
+        let point = point.slice(s![..dimensions]).to_vec();
+        let nearest = kdtree.nearest(&point, 1, &squared_euclidean);
         if let Ok(nearest) = nearest {
             if let Some(nearest) = nearest.first() {
                 result.push(*nearest.1);
@@ -28,7 +30,7 @@ pub fn nearest_neighbours(reference: &Array2<f64>, new: &Array2<f64>) -> Array1<
     Array1::from_vec(result)
 }
 
-// performs one iteration of ICP algorithm to find transform from target to reference
+// performs one iteration of ICP algorithm (2D or 3D) to find transform from target to reference
 // uses SVD-based algorithm described in Least-Squares Fitting of Two 3-D Point Sets by K. S. ARUN
 pub fn find_transform(
     from: &Array2<f64>,
@@ -51,17 +53,77 @@ pub fn find_transform(
     let svd = h.svd(true, true).unwrap();
     let u = svd.0.unwrap();
     let vt = svd.2.unwrap();
-    let v = vt.t();
+    let mut v = vt.t().to_owned();
     let x = v.dot(&u.t());
     let det_x = x.det().unwrap();
 
     let rmat = if abs_diff_eq!(det_x, 1., epsilon = 0.00001) {
         x
     } else {
-        println!("{}", det_x);
-        panic!("Algorithm failed since det(x) != +1");
-    }; // TODO: solve the non-1 case
+        // det(x) = -1 is a reflection, which happens for coplanar 3D points or very noisy clouds.
+        // Flipping the singular vector with the smallest singular value gives the closest rotation.
+        let last = v.ncols() - 1;
+        v.column_mut(last).mapv_inplace(|e| -e);
+        v.dot(&u.t())
+    };
     let t = p_dash - rmat.dot(&p);
 
     rmat_and_tvec_to_tmat(&rmat, &t)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // homogeneous 3D cloud spread over a 2 m cube, so no three points line up and no four share a
+    // plane
+    fn random_cloud(count: usize, seed: u64) -> Array2<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Array2::from_shape_fn((count, 4), |(_, col)| {
+            if col == 3 {
+                1.
+            } else {
+                rng.gen_range(-1.0..1.0)
+            }
+        })
+    }
+
+    #[test]
+    fn nearest_neighbours_in_3d() {
+        let reference = random_cloud(50, 1);
+        let mut nudged = reference.clone();
+        nudged.slice_mut(s![.., ..3]).mapv_inplace(|e| e + 1e-4);
+        let correspondences = nearest_neighbours(&reference, &nudged);
+        assert_eq!(correspondences, Array1::from_iter(0..50));
+    }
+
+    #[test]
+    fn find_transform_recovers_3d_rotation_and_translation() {
+        let from = random_cloud(30, 2);
+        let rmat = roll_pitch_yaw_to_rmat(0.3, -0.2, 0.5);
+        let tmat = rmat_and_tvec_to_tmat(&rmat, &array![1., -2., 0.5]);
+        let to = transformed_cloud(&from, &tmat);
+        let correspondences = nearest_neighbours(&from, &to);
+        let found = find_transform(&from, &to, &correspondences);
+        for (a, b) in found.iter().zip(tmat.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn find_transform_returns_rotation_for_mirrored_cloud() {
+        // the best orthogonal fit to a mirror image is a reflection, which must not be returned
+        let from = random_cloud(30, 3);
+        let mut to = from.clone();
+        to.column_mut(2).mapv_inplace(|z| -z);
+        let found = find_transform(&from, &to, &Array1::from_iter(0..30));
+        let (rmat, _) = tmat_to_rmat_and_tvec(&found);
+        for (a, b) in rmat.dot(&rmat.t()).iter().zip(Array2::<f64>::eye(3).iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-9);
+        }
+        assert_abs_diff_eq!(rmat.det().unwrap(), 1., epsilon = 1e-9);
+    }
+}
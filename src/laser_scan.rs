@@ -0,0 +1,171 @@
+use crate::diff_drive::Pose;
+use crate::trajectory::PoseTrajectory;
+use ndarray::prelude::*;
+
+// A single planar laser scan in the sensor frame, laid out like the ROS sensor_msgs/LaserScan
+// message.
+// Beam i points angle_min + i * angle_increment radians anticlockwise from the sensor heading.
+// Ranges outside [range_min, range_max) are invalid, with no return reported as infinity.
+// Beam i is captured at timestamp + i * time_increment, which is zero for an instantaneous scan.
+#[derive(Clone, Debug)]
+pub struct LaserScan {
+    pub timestamp: f64, // seconds, capture time of the first beam
+    pub angle_min: f32,
+    pub angle_increment: f32,
+    pub time_increment: f32, // seconds between beams
+    pub scan_time: f32,      // seconds between scans
+    pub range_min: f32,
+    pub range_max: f32,
+    pub ranges: Vec<f32>,
+    pub intensities: Option<Vec<f32>>,
+}
+
+impl LaserScan {
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn angle_max(&self) -> f32 {
+        self.angle(self.len().saturating_sub(1))
+    }
+
+    pub fn angle(&self, index: usize) -> f32 {
+        self.angle_min + index as f32 * self.angle_increment
+    }
+
+    pub fn beam_time(&self, index: usize) -> f64 {
+        self.timestamp + index as f64 * self.time_increment as f64
+    }
+
+    pub fn is_valid(&self, range: f32) -> bool {
+        range >= self.range_min && range < self.range_max
+    }
+
+    // (angle, range) of each valid return
+    pub fn valid_returns(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        self.ranges
+            .iter()
+            .enumerate()
+            .filter(move |(_, range)| self.is_valid(**range))
+            .map(move |(i, range)| (self.angle(i), *range))
+    }
+
+    // homogeneous cloud (dimension nx3) of the valid returns in the sensor frame, ready for icp
+    pub fn to_cloud(&self) -> Array2<f64> {
+        self.to_cloud_at(Pose::default())
+    }
+
+    // homogeneous cloud (dimension nx3) of the valid returns, given the pose of the sensor
+    pub fn to_cloud_at(&self, sensor_pose: Pose) -> Array2<f64> {
+        let points: Vec<[f64; 3]> = self
+            .valid_returns()
+            .map(|(angle, range)| {
+                let angle = (sensor_pose.theta + angle) as f64;
+                let range = range as f64;
+                [
+                    sensor_pose.x as f64 + range * angle.cos(),
+                    sensor_pose.y as f64 + range * angle.sin(),
+                    1.,
+                ]
+            })
+            .collect();
+        Array2::from(points)
+    }
+
+    // Removes motion distortion given the trajectory of the sensor (not the robot base) over the
+    // scan: each return is placed using the sensor pose at its capture time, then expressed in the
+    // sensor frame at the scan timestamp. None if the trajectory does not cover the scan.
+    pub fn deskewed_cloud(&self, sensor_trajectory: &PoseTrajectory) -> Option<Array2<f64>> {
+        let reference = sensor_trajectory.pose_at(self.timestamp)?.inverse();
+        let mut points = Vec::with_capacity(self.len());
+        for (i, range) in self.ranges.iter().enumerate() {
+            if !self.is_valid(*range) {
+                continue;
+            }
+            let sensor_pose = sensor_trajectory.pose_at(self.beam_time(i))?;
+            let angle = self.angle(i);
+            let point = Pose {
+                x: range * angle.cos(),
+                y: range * angle.sin(),
+                theta: 0.,
+            };
+            let corrected = reference.compose(sensor_pose).compose(point);
+            points.push([corrected.x as f64, corrected.y as f64, 1.]);
+        }
+        Some(Array2::from(points))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    // four beams a quarter turn apart, starting behind the sensor
+    fn scan(ranges: Vec<f32>) -> LaserScan {
+        LaserScan {
+            timestamp: 0.,
+            angle_min: -PI,
+            angle_increment: FRAC_PI_2,
+            time_increment: 0.,
+            scan_time: 0.1,
+            range_min: 0.5,
+            range_max: 4.,
+            ranges,
+            intensities: None,
+        }
+    }
+
+    #[test]
+    fn is_valid_includes_range_min_but_not_range_max() {
+        let scan = scan(vec![]);
+        assert!(scan.is_valid(0.5));
+        assert!(scan.is_valid(3.999));
+        assert!(!scan.is_valid(4.));
+        assert!(!scan.is_valid(0.499));
+        assert!(!scan.is_valid(f32::INFINITY));
+        assert!(!scan.is_valid(f32::NAN));
+    }
+
+    #[test]
+    fn valid_returns_skip_invalid_beams() {
+        let scan = scan(vec![1., f32::INFINITY, 0.2, 2.]);
+        let returns: Vec<(f32, f32)> = scan.valid_returns().collect();
+        assert_eq!(returns.len(), 2);
+        assert_abs_diff_eq!(returns[0].0, -PI);
+        assert_abs_diff_eq!(returns[0].1, 1.);
+        assert_abs_diff_eq!(returns[1].0, FRAC_PI_2);
+        assert_abs_diff_eq!(returns[1].1, 2.);
+    }
+
+    #[test]
+    fn to_cloud_at_places_returns_around_the_sensor_pose() {
+        let scan = scan(vec![1., f32::INFINITY, 3., 2.]);
+        let pose = Pose {
+            x: 1.,
+            y: 2.,
+            theta: FRAC_PI_2,
+        };
+        let cloud = scan.to_cloud_at(pose);
+        // behind the sensor, straight ahead and to its left, with the sensor facing +y
+        let expected = array![[1., 1., 1.], [1., 5., 1.], [-1., 2., 1.]];
+        assert_eq!(cloud.dim(), expected.dim());
+        for (a, b) in cloud.iter().zip(expected.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-6);
+        }
+        assert_eq!(scan.to_cloud().nrows(), 3);
+    }
+
+    #[test]
+    fn angle_max_is_the_last_beam() {
+        assert_abs_diff_eq!(scan(vec![1.; 4]).angle_max(), FRAC_PI_2);
+        assert_abs_diff_eq!(scan(vec![1.]).angle_max(), -PI);
+        // no beams, so fall back to angle_min rather than underflowing the index
+        assert_abs_diff_eq!(scan(vec![]).angle_max(), -PI);
+    }
+}
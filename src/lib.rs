@@ -1,6 +1,18 @@
+pub mod controller;
 pub mod diff_drive;
+pub mod distance_transform;
 pub mod draw;
+pub mod ekf_slam;
 pub mod icp;
+pub mod laser_scan;
 pub mod lidar;
+pub mod likelihood_field;
+pub mod map_builder;
+pub mod mcl;
+pub mod measurement_model;
+pub mod motion_model;
+pub mod occupancy_grid;
 pub mod pose_graph;
+pub mod rbpf_slam;
+pub mod trajectory;
 pub mod transforms;
@@ -1,7 +1,22 @@
-use iter_num_tools::{arange, lin_space};
-use nannou::prelude::{pt2, Point2};
-use rand::thread_rng;
-use rand_distr::{Distribution, Normal};
+use crate::diff_drive::Pose;
+use crate::laser_scan::LaserScan;
+use crate::measurement_model::{BeamModel, MeasurementModel};
+use dynamic::DynamicObject;
+use ndarray::Array2;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::f32::consts::PI;
+
+pub mod dynamic;
+pub mod landmarks;
+pub mod map;
+pub mod vector;
+
+// Anything a lidar beam can be cast into
+pub trait RayCast {
+    // distance along the ray heading to the first obstacle, or None if there is none in max_range
+    fn cast_ray(&self, ray: Pose, max_range: f32) -> Option<f32>;
+}
 
 #[derive(Copy, Clone, Debug)]
 pub struct Dimension {
@@ -9,60 +24,679 @@ pub struct Dimension {
     pub height: usize,
 }
 
-pub fn point_to_pixel_coords(mouse_pos: Point2, environment_dims: Dimension) -> Option<PixelCoord> {
-    let in_bounds_x = mouse_pos.x > -(environment_dims.width as f32 / 2.)
-        && mouse_pos.x < environment_dims.width as f32 / 2.;
-    let in_bounds_y = mouse_pos.y > -(environment_dims.height as f32 / 2.)
-        && mouse_pos.y < environment_dims.height as f32 / 2.;
-    if !in_bounds_x || !in_bounds_y {
-        return None;
+// Sensor model parameters. Distances are in metres, angles in radians from the sensor heading.
+#[derive(Copy, Clone, Debug)]
+pub struct LidarConfig {
+    pub mount: Pose, // sensor pose relative to the robot base
+    pub beam_count: usize,
+    pub fov: f32,
+    pub angle_offset: f32, // angle of the first beam
+    pub min_range: f32,
+    pub max_range: f32,
+    pub range_noise_std_dev: f32,
+    pub scan_period: f32, // seconds per revolution, beams are captured as the head turns past them
+    pub seed: Option<u64>, // None seeds from entropy
+}
+
+impl Default for LidarConfig {
+    // 128 noise-free beams over 360 degrees with a 2.5 m range, captured instantaneously
+    fn default() -> Self {
+        LidarConfig {
+            mount: Pose::default(),
+            beam_count: 128,
+            fov: 2. * PI,
+            angle_offset: 0.,
+            min_range: 0.,
+            max_range: 2.5,
+            range_noise_std_dev: 0.,
+            scan_period: 0.,
+            seed: None,
+        }
     }
+}
 
-    let x = (mouse_pos.x + environment_dims.width as f32 / 2.0).floor() as usize;
-    let y = (-mouse_pos.y + environment_dims.height as f32 / 2.0).floor() as usize;
+impl LidarConfig {
+    // Slamtec RPLidar A1: 360 degrees at ~1 degree resolution, 0.15-12 m, 5.5 Hz
+    pub fn rplidar_a1() -> Self {
+        LidarConfig {
+            mount: Pose::default(),
+            beam_count: 360,
+            fov: 2. * PI,
+            angle_offset: 0.,
+            min_range: 0.15,
+            max_range: 12.,
+            range_noise_std_dev: 0.005,
+            scan_period: 1. / 5.5,
+            seed: None,
+        }
+    }
+
+    // Hokuyo UTM-30LX: 270 degrees at 0.25 degree resolution, 0.1-30 m, 40 Hz
+    pub fn hokuyo_utm_30lx() -> Self {
+        LidarConfig {
+            mount: Pose::default(),
+            beam_count: 1081,
+            fov: 1.5 * PI,
+            angle_offset: -0.75 * PI,
+            min_range: 0.1,
+            max_range: 30.,
+            range_noise_std_dev: 0.01,
+            scan_period: 0.025,
+            seed: None,
+        }
+    }
 
-    Some(PixelCoord { x, y })
+    // Builds the beam count from a desired angular resolution (radians) instead
+    pub fn with_angular_resolution(mut self, resolution: f32) -> Self {
+        self.beam_count = if self.is_full_circle() {
+            (self.fov / resolution).round() as usize
+        } else {
+            (self.fov / resolution).round() as usize + 1
+        };
+        self
+    }
+
+    // Angle between successive beams. A full circle does not repeat the first beam, a partial fov
+    // includes both ends.
+    pub fn angular_resolution(&self) -> f32 {
+        if self.is_full_circle() || self.beam_count < 2 {
+            self.fov / self.beam_count as f32
+        } else {
+            self.fov / (self.beam_count - 1) as f32
+        }
+    }
+
+    pub fn beam_angles(&self) -> impl Iterator<Item = f32> {
+        let angle_offset = self.angle_offset;
+        let resolution = self.angular_resolution();
+        (0..self.beam_count).map(move |i| angle_offset + i as f32 * resolution)
+    }
+
+    fn is_full_circle(&self) -> bool {
+        self.fov >= 2. * PI - 1e-6
+    }
 }
 
-pub fn scan_from_point(origin: Point2, environment: &Environment) -> Vec<Point2> {
-    let scan_angles = lin_space(0.0..std::f32::consts::PI * 2.0, 128);
-    let max_scan_distance = 250.;
-    let scan_step = 1.;
-    let scan_range = arange(0.0..max_scan_distance, scan_step);
+// Simulated lidar, owns the random number generator so repeated scans get fresh but reproducible
+// noise
+pub struct Lidar {
+    pub config: LidarConfig,
+    pub model: Box<dyn MeasurementModel>,
+    rng: StdRng,
+}
 
-    let mut scan_points = vec![];
-    let scan_range: Vec<f32> = scan_range.collect();
+impl Lidar {
+    // Gaussian range noise with the configured std dev
+    pub fn new(config: LidarConfig) -> Self {
+        let model = BeamModel::gaussian(config.range_noise_std_dev);
+        Lidar::with_model(config, Box::new(model))
+    }
 
-    let mut rng = thread_rng();
-    let normal = Normal::new(0.0, 0.0).unwrap();
+    pub fn with_model(config: LidarConfig, model: Box<dyn MeasurementModel>) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Lidar { config, model, rng }
+    }
 
-    for angle in scan_angles {
-        for dist in &scan_range {
-            let scan_ray = pt2(angle.cos(), angle.sin());
-            let scan_point = origin + *dist * scan_ray;
-            let scan_coords = point_to_pixel_coords(scan_point, environment.dimensions);
-            if let Some(scan_coords) = scan_coords {
-                let object_detected = environment.grid[[scan_coords.y, scan_coords.x]];
-                if object_detected {
-                    let noise = normal.sample(&mut rng);
-                    let noisy_point = origin + (dist + noise) * scan_ray;
-                    scan_points.push(noisy_point);
-                    break;
-                }
-            }
+    // pose of the sensor in the world given the pose of the robot base
+    pub fn sensor_pose(&self, robot_pose: Pose) -> Pose {
+        robot_pose.compose(self.config.mount)
+    }
+
+    // scan from the robot base at the given pose and time, ranges are in the sensor frame
+    pub fn scan<E: RayCast + ?Sized>(
+        &mut self,
+        robot_pose: Pose,
+        timestamp: f64,
+        environment: &E,
+    ) -> LaserScan {
+        self.scan_during_motion(|_| robot_pose, timestamp, environment)
+    }
+
+    // Scan while the robot moves, as a spinning lidar does: beam i is captured at
+    // timestamp + i * time_increment from the robot pose at that time, so the scan is skewed. The
+    // head turns a full circle every scan_period, including any part of it outside the fov.
+    pub fn scan_during_motion<E: RayCast + ?Sized>(
+        &mut self,
+        robot_pose_at: impl Fn(f64) -> Pose,
+        timestamp: f64,
+        environment: &E,
+    ) -> LaserScan {
+        let config = self.config;
+        let time_increment = if config.beam_count > 0 {
+            config.scan_period * config.angular_resolution() / (2. * PI)
+        } else {
+            0.
+        };
+
+        let mut ranges = Vec::with_capacity(config.beam_count);
+        for (i, bearing) in config.beam_angles().enumerate() {
+            let beam_time = timestamp + i as f64 * time_increment as f64;
+            let sensor_pose = self.sensor_pose(robot_pose_at(beam_time));
+            let cast_bearing = self.model.perturb_bearing(bearing, &mut self.rng);
+            let ray = Pose {
+                theta: sensor_pose.theta + cast_bearing,
+                ..sensor_pose
+            };
+            let true_range = environment
+                .cast_ray(ray, config.max_range)
+                .unwrap_or(config.max_range);
+            let range = self
+                .model
+                .measure_range(true_range, config.max_range, &mut self.rng);
+            ranges.push(if range < config.max_range {
+                range
+            } else {
+                f32::INFINITY
+            });
+        }
+
+        LaserScan {
+            timestamp,
+            angle_min: config.angle_offset,
+            angle_increment: config.angular_resolution(),
+            time_increment,
+            scan_time: config.scan_period,
+            range_min: config.min_range,
+            range_max: config.max_range,
+            ranges,
+            intensities: None,
         }
     }
+}
 
-    scan_points
+// world frame cloud using the default sensor model, facing along the x axis
+pub fn scan_from_point<E: RayCast + ?Sized>(x: f32, y: f32, environment: &E) -> Array2<f64> {
+    let pose = Pose { x, y, theta: 0. };
+    let scan = Lidar::new(LidarConfig::default()).scan(pose, 0., environment);
+    scan.to_cloud_at(pose)
 }
 
-#[derive(Copy, Clone)]
+// cell index in the grid, x is the column and y is the row counted from the top of the image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct PixelCoord {
     pub x: usize,
     pub y: usize,
 }
 
-pub struct Environment {
-    pub grid: ndarray::Array2<bool>,
+// Placement of a grid in the world, set up like a ROS map: each cell is resolution metres square
+// and origin is the world pose of the bottom left corner of the bottom left cell. Row 0 is the top
+// row, as in the map image.
+#[derive(Copy, Clone, Debug)]
+pub struct GridGeometry {
     pub dimensions: Dimension,
+    pub resolution: f32, // metres per cell
+    pub origin: Pose,
+}
+
+impl GridGeometry {
+    // grid with world (0, 0) at its centre
+    pub fn centred(dimensions: Dimension, resolution: f32) -> Self {
+        let origin = Pose {
+            x: -(dimensions.width as f32) * resolution / 2.,
+            y: -(dimensions.height as f32) * resolution / 2.,
+            theta: 0.,
+        };
+        GridGeometry {
+            dimensions,
+            resolution,
+            origin,
+        }
+    }
+
+    // continuous grid coordinates of a world point, in cells right of the left edge and down from
+    // the top edge
+    pub(crate) fn world_to_continuous(&self, x: f32, y: f32) -> (f32, f32) {
+        let (s, c) = self.origin.theta.sin_cos();
+        let dx = x - self.origin.x;
+        let dy = y - self.origin.y;
+        let map_x = c * dx + s * dy;
+        let map_y = -s * dx + c * dy;
+        (
+            map_x / self.resolution,
+            self.dimensions.height as f32 - map_y / self.resolution,
+        )
+    }
+
+    pub fn world_to_grid(&self, x: f32, y: f32) -> Option<PixelCoord> {
+        let (grid_x, grid_y) = self.world_to_continuous(x, y);
+        let in_bounds = grid_x >= 0.
+            && grid_x < self.dimensions.width as f32
+            && grid_y >= 0.
+            && grid_y < self.dimensions.height as f32;
+        if !in_bounds {
+            return None;
+        }
+        Some(PixelCoord {
+            x: grid_x.floor() as usize,
+            y: grid_y.floor() as usize,
+        })
+    }
+
+    // world position of the centre of a cell
+    pub fn grid_to_world(&self, coord: PixelCoord) -> (f32, f32) {
+        let map_x = (coord.x as f32 + 0.5) * self.resolution;
+        let map_y = (self.dimensions.height as f32 - coord.y as f32 - 0.5) * self.resolution;
+        let (s, c) = self.origin.theta.sin_cos();
+        (
+            self.origin.x + c * map_x - s * map_y,
+            self.origin.y + s * map_x + c * map_y,
+        )
+    }
+}
+
+// Occupancy grid of the world, placed as described by GridGeometry. Dynamic objects move on top
+// of the static grid and are seen by the lidar but not stored in the grid. Landmarks are points
+// seen only by a landmarks::LandmarkSensor, for feature-based SLAM.
+#[derive(Clone, Debug)]
+pub struct Environment {
+    pub grid: Array2<bool>,
+    pub geometry: GridGeometry, // dimensions match the grid
+    pub dynamic_objects: Vec<DynamicObject>,
+    pub landmarks: Vec<[f32; 2]>, // world positions
+}
+
+impl Environment {
+    pub fn new(grid: Array2<bool>, resolution: f32, origin: Pose) -> Self {
+        let dimensions = Dimension {
+            width: grid.ncols(),
+            height: grid.nrows(),
+        };
+        Environment {
+            grid,
+            geometry: GridGeometry {
+                dimensions,
+                resolution,
+                origin,
+            },
+            dynamic_objects: vec![],
+            landmarks: vec![],
+        }
+    }
+
+    // environment with world (0, 0) at the centre of the grid
+    pub fn centred(grid: Array2<bool>, resolution: f32) -> Self {
+        let dimensions = Dimension {
+            width: grid.ncols(),
+            height: grid.nrows(),
+        };
+        let origin = GridGeometry::centred(dimensions, resolution).origin;
+        Environment::new(grid, resolution, origin)
+    }
+
+    fn world_to_continuous(&self, x: f32, y: f32) -> (f32, f32) {
+        self.geometry.world_to_continuous(x, y)
+    }
+
+    pub fn world_to_grid(&self, x: f32, y: f32) -> Option<PixelCoord> {
+        self.geometry.world_to_grid(x, y)
+    }
+
+    // world position of the centre of a cell
+    pub fn grid_to_world(&self, coord: PixelCoord) -> (f32, f32) {
+        self.geometry.grid_to_world(coord)
+    }
+
+    // whether a world point is outside the map or in an occupied cell of the static grid
+    pub fn is_blocked(&self, x: f32, y: f32) -> bool {
+        match self.world_to_grid(x, y) {
+            Some(coord) => self.grid[[coord.y, coord.x]],
+            None => true,
+        }
+    }
+
+    // advance the dynamic objects by one simulation step
+    pub fn step(&mut self, time_step: f32) {
+        let mut objects = std::mem::take(&mut self.dynamic_objects);
+        for object in &mut objects {
+            object.step(time_step, |x, y| self.is_blocked(x, y));
+        }
+        self.dynamic_objects = objects;
+    }
+
+    // static grid plus the current footprint of the dynamic objects flagged in_ground_truth
+    pub fn ground_truth(&self) -> Array2<bool> {
+        let mut grid = self.grid.clone();
+        for object in self.dynamic_objects.iter().filter(|o| o.in_ground_truth) {
+            let reach = object.bounding_radius();
+            let cells = (reach / self.geometry.resolution).ceil() as isize + 1;
+            let centre = match self.world_to_grid(object.pose.x, object.pose.y) {
+                Some(centre) => centre,
+                None => continue,
+            };
+            for row in centre.y as isize - cells..=centre.y as isize + cells {
+                for col in centre.x as isize - cells..=centre.x as isize + cells {
+                    let in_bounds = row >= 0
+                        && col >= 0
+                        && (row as usize) < self.geometry.dimensions.height
+                        && (col as usize) < self.geometry.dimensions.width;
+                    if !in_bounds {
+                        continue;
+                    }
+                    let coord = PixelCoord {
+                        x: col as usize,
+                        y: row as usize,
+                    };
+                    let (x, y) = self.grid_to_world(coord);
+                    if object.contains(x, y) {
+                        grid[[coord.y, coord.x]] = true;
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    // Distance from the ray origin to the first occupied cell of the static grid along the ray
+    // heading, or None if the ray leaves the map or exceeds max_range first. Visits every cell the
+    // ray passes through exactly once using the traversal from A Fast Voxel Traversal Algorithm
+    // for Ray Tracing by J. Amanatides and A. Woo, so thin diagonal walls are never skipped.
+    pub fn cast_ray_static(&self, ray: Pose, max_range: f32) -> Option<f32> {
+        let (start_x, start_y) = self.world_to_continuous(ray.x, ray.y);
+        let GridGeometry {
+            dimensions,
+            resolution,
+            origin,
+        } = self.geometry;
+        let width = dimensions.width as f32;
+        let height = dimensions.height as f32;
+        if start_x < 0. || start_x >= width || start_y < 0. || start_y >= height {
+            return None;
+        }
+        let angle = ray.theta - origin.theta;
+        let dir_x = angle.cos();
+        let dir_y = -angle.sin();
+        let max_cells = max_range / resolution;
+
+        let mut cell_x = start_x.floor() as isize;
+        let mut cell_y = start_y.floor() as isize;
+        let (step_x, mut t_max_x, t_delta_x) = traversal_params(start_x, cell_x, dir_x);
+        let (step_y, mut t_max_y, t_delta_y) = traversal_params(start_y, cell_y, dir_y);
+
+        let mut t = 0.;
+        loop {
+            if self.grid[[cell_y as usize, cell_x as usize]] {
+                return Some(t * resolution);
+            }
+            if t_max_x < t_max_y {
+                t = t_max_x;
+                t_max_x += t_delta_x;
+                cell_x += step_x;
+            } else {
+                t = t_max_y;
+                t_max_y += t_delta_y;
+                cell_y += step_y;
+            }
+            let in_bounds = cell_x >= 0
+                && cell_x < dimensions.width as isize
+                && cell_y >= 0
+                && cell_y < dimensions.height as isize;
+            if t > max_cells || !in_bounds {
+                return None;
+            }
+        }
+    }
+}
+
+impl RayCast for Environment {
+    // nearest of the static grid and the dynamic objects
+    fn cast_ray(&self, ray: Pose, max_range: f32) -> Option<f32> {
+        self.dynamic_objects
+            .iter()
+            .filter_map(|object| object.intersect(ray))
+            .chain(self.cast_ray_static(ray, max_range))
+            .filter(|dist| *dist <= max_range)
+            .fold(None, |nearest: Option<f32>, dist| {
+                Some(nearest.map_or(dist, |n| n.min(dist)))
+            })
+    }
+}
+
+// step direction, distance along the ray to the first boundary of the starting cell, and distance
+// between boundaries
+pub(crate) fn traversal_params(start: f32, cell: isize, dir: f32) -> (isize, f32, f32) {
+    if dir > 0. {
+        (1, (cell as f32 + 1. - start) / dir, 1. / dir)
+    } else if dir < 0. {
+        (-1, (start - cell as f32) / -dir, -1. / dir)
+    } else {
+        (0, f32::INFINITY, f32::INFINITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement_model::NoiseFree;
+    use crate::trajectory::PoseTrajectory;
+    use approx::assert_abs_diff_eq;
+    use std::f32::consts::FRAC_PI_4;
+
+    const RESOLUTION: f32 = 0.1;
+
+    // 10 x 10 cell environment with its bottom left corner at the world origin
+    fn environment(occupied: &[(usize, usize)]) -> Environment {
+        let mut grid = Array2::from_elem((10, 10), false);
+        for &(row, col) in occupied {
+            grid[[row, col]] = true;
+        }
+        Environment::new(grid, RESOLUTION, Pose::default())
+    }
+
+    fn ray(x: f32, y: f32, theta: f32) -> Pose {
+        Pose { x, y, theta }
+    }
+
+    fn walled_box() -> Environment {
+        let walls: Vec<(usize, usize)> = (0..10)
+            .flat_map(|i| vec![(0, i), (9, i), (i, 0), (i, 9)])
+            .collect();
+        environment(&walls)
+    }
+
+    #[test]
+    fn axis_aligned_rays_stop_at_the_wall_face() {
+        let environment = walled_box();
+        let cases = [(0., 0.65), (PI / 2., 0.35), (PI, 0.15), (-PI / 2., 0.45)];
+        for &(theta, expected) in &cases {
+            let range = environment
+                .cast_ray_static(ray(0.25, 0.55, theta), 5.)
+                .unwrap();
+            assert_abs_diff_eq!(range, expected, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn diagonal_rays_do_not_leak_through_a_one_cell_wall() {
+        // cells that only touch at their corners, a line from the top left to the bottom right
+        let diagonal: Vec<(usize, usize)> = (0..10).map(|i| (i, i)).collect();
+        let environment = environment(&diagonal);
+
+        // exactly through the corner shared by two wall cells
+        let range = environment
+            .cast_ray_static(ray(0.25, 0.25, FRAC_PI_4), 5.)
+            .unwrap();
+        assert_abs_diff_eq!(range, 0.25 * 2f32.sqrt(), epsilon = 1e-5);
+
+        // every ray heading up and right from below the wall hits it where a fine march does,
+        // including those that only clip the corner of a wall cell
+        for i in 1..90 {
+            let theta = (i as f32).to_radians();
+            let range = environment
+                .cast_ray_static(ray(0.72, 0.13, theta), 5.)
+                .unwrap_or_else(|| panic!("ray at {} degrees passed the wall", i));
+            let marched = (0..100_000)
+                .map(|step| step as f32 * 1e-5)
+                .find(|d| environment.is_blocked(0.72 + d * theta.cos(), 0.13 + d * theta.sin()))
+                .unwrap();
+            assert_abs_diff_eq!(range, marched, epsilon = 2e-5);
+        }
+    }
+
+    #[test]
+    fn rays_return_nothing_beyond_max_range() {
+        let environment = walled_box();
+        assert_eq!(environment.cast_ray_static(ray(0.25, 0.55, 0.), 0.6), None);
+        let range = environment.cast_ray_static(ray(0.25, 0.55, 0.), 0.7);
+        assert_abs_diff_eq!(range.unwrap(), 0.65, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn ray_starting_in_an_occupied_cell_hits_immediately() {
+        let environment = walled_box();
+        assert_eq!(
+            environment.cast_ray_static(ray(0.05, 0.55, 0.), 5.),
+            Some(0.)
+        );
+    }
+
+    #[test]
+    fn rays_leaving_the_grid_return_nothing() {
+        let environment = environment(&[]);
+        for i in 0..16 {
+            let theta = i as f32 * PI / 8.;
+            assert_eq!(
+                environment.cast_ray_static(ray(0.33, 0.61, theta), 5.),
+                None
+            );
+        }
+        // from outside the grid, even pointing into it
+        assert_eq!(environment.cast_ray_static(ray(-0.5, 0.5, 0.), 5.), None);
+    }
+
+    #[test]
+    fn cast_ray_includes_dynamic_objects() {
+        let mut environment = walled_box();
+        environment.dynamic_objects.push(DynamicObject::new(
+            dynamic::Shape::Circle { radius: 0.1 },
+            ray(0.55, 0.55, 0.),
+            dynamic::Trajectory::Stationary,
+            0,
+        ));
+        let range = environment.cast_ray(ray(0.25, 0.55, 0.), 5.).unwrap();
+        assert_abs_diff_eq!(range, 0.2, epsilon = 1e-5);
+        // the object is behind the ray
+        let range = environment.cast_ray(ray(0.25, 0.55, PI), 5.).unwrap();
+        assert_abs_diff_eq!(range, 0.15, epsilon = 1e-5);
+    }
+
+    // times at which each beam of a scan is captured
+    fn beam_times(config: LidarConfig) -> (LaserScan, Vec<f64>) {
+        let times = std::cell::RefCell::new(vec![]);
+        let mut lidar = Lidar::new(config);
+        let scan = lidar.scan_during_motion(
+            |time| {
+                times.borrow_mut().push(time);
+                Pose::default()
+            },
+            1.,
+            &walled_box(),
+        );
+        (scan, times.into_inner())
+    }
+
+    #[test]
+    fn partial_fov_beams_cover_part_of_the_scan_period() {
+        // 1081 beams at 0.25 degree spacing over 270 degrees
+        let config = LidarConfig::hokuyo_utm_30lx();
+        let (scan, times) = beam_times(config);
+        assert_abs_diff_eq!(scan.time_increment, 0.025 / 1440., epsilon = 1e-10);
+        assert_eq!(times.len(), 1081);
+        assert_abs_diff_eq!(times[0], 1.);
+        assert_abs_diff_eq!(times[1080] - times[0], 0.75 * 0.025, epsilon = 1e-7);
+    }
+
+    #[test]
+    fn full_circle_beams_cover_the_scan_period() {
+        let config = LidarConfig::rplidar_a1();
+        let (scan, times) = beam_times(config);
+        assert_abs_diff_eq!(
+            scan.time_increment * 360.,
+            config.scan_period,
+            epsilon = 1e-6
+        );
+        // the next scan's first beam would follow one increment after the last
+        let sweep = times[359] - times[0] + scan.time_increment as f64;
+        assert_abs_diff_eq!(sweep, config.scan_period as f64, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn deskewing_a_moving_scan_recovers_the_static_scan() {
+        // 4 x 4 m room with one-cell walls, inner faces at +-1.95 m
+        let mut grid = Array2::from_elem((80, 80), false);
+        for i in 0..80 {
+            for &(row, col) in &[(0, i), (79, i), (i, 0), (i, 79)] {
+                grid[[row, col]] = true;
+            }
+        }
+        let room = Environment::centred(grid, 0.05);
+        let config = LidarConfig {
+            mount: Pose {
+                x: 0.1,
+                y: 0.05,
+                theta: 0.3,
+            },
+            beam_count: 360,
+            max_range: 5.,
+            scan_period: 0.1,
+            ..LidarConfig::default()
+        };
+        let mut lidar = Lidar::with_model(config, Box::new(NoiseFree));
+
+        // driving a 1 m radius arc at 1 m/s from timestamp on
+        let timestamp = 2.;
+        let robot_pose_at = |time: f64| {
+            let turned = (time - timestamp) as f32;
+            let (x0, y0, theta0) = (-0.5, -0.3, 0.2);
+            Pose {
+                x: x0 + (theta0 + turned).sin() - theta0.sin(),
+                y: y0 - (theta0 + turned).cos() + theta0.cos(),
+                theta: theta0 + turned,
+            }
+        };
+        let skewed = lidar.scan_during_motion(robot_pose_at, timestamp, &room);
+        let static_cloud = lidar
+            .scan(robot_pose_at(timestamp), timestamp, &room)
+            .to_cloud();
+
+        let mut sensor_trajectory = PoseTrajectory::new();
+        for i in 0..=200 {
+            let time = timestamp + i as f64 * 0.0005;
+            sensor_trajectory.push(time, lidar.sensor_pose(robot_pose_at(time)));
+        }
+        let deskewed = skewed.deskewed_cloud(&sensor_trajectory).unwrap();
+
+        // largest distance from a point of the cloud to the nearest point of the static scan
+        let furthest_from_static = |cloud: &Array2<f64>| {
+            cloud
+                .outer_iter()
+                .map(|p| {
+                    static_cloud
+                        .outer_iter()
+                        .map(|q| (p[0] - q[0]).hypot(p[1] - q[1]))
+                        .fold(f64::INFINITY, f64::min)
+                })
+                .fold(0., f64::max)
+        };
+        // neighbouring static returns are at most about 3.5 cm apart
+        assert_eq!(deskewed.nrows(), static_cloud.nrows());
+        assert!(furthest_from_static(&deskewed) < 0.03);
+        assert!(furthest_from_static(&skewed.to_cloud()) > 0.1);
+
+        // and each deskewed return lies on a wall face, seen from the sensor at the timestamp
+        let reference = lidar.sensor_pose(robot_pose_at(timestamp));
+        for p in deskewed.outer_iter() {
+            let point = reference.compose(Pose {
+                x: p[0] as f32,
+                y: p[1] as f32,
+                theta: 0.,
+            });
+            let off_wall = (point.x.abs() - 1.95)
+                .abs()
+                .min((point.y.abs() - 1.95).abs());
+            assert!(off_wall < 1e-3, "{:?} is off the walls", point);
+        }
+    }
 }
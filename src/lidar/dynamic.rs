@@ -0,0 +1,251 @@
+// Moving obstacles such as people and doors, ray cast alongside the static map
+use crate::diff_drive::Pose;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, Normal};
+
+// Outline of an object about its pose, boxes are rotated by the pose heading
+#[derive(Copy, Clone, Debug)]
+pub enum Shape {
+    Circle { radius: f32 },
+    Box { half_length: f32, half_width: f32 },
+}
+
+#[derive(Clone, Debug)]
+pub enum Trajectory {
+    Stationary,
+    // drive through the waypoints at constant speed (m/s), restarting from the first if looped
+    Waypoints {
+        points: Vec<[f32; 2]>,
+        speed: f32,
+        looped: bool,
+    },
+    // constant speed (m/s) with the heading perturbed by turn_std_dev (rad/sqrt(s)), turning back
+    // on contact with the map
+    RandomWalk {
+        speed: f32,
+        turn_std_dev: f32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct DynamicObject {
+    pub shape: Shape,
+    pub pose: Pose,
+    pub trajectory: Trajectory,
+    // include in Environment::ground_truth, e.g. for furniture but not people
+    pub in_ground_truth: bool,
+    next_waypoint: usize,
+    rng: StdRng,
+}
+
+impl DynamicObject {
+    pub fn new(shape: Shape, pose: Pose, trajectory: Trajectory, seed: u64) -> Self {
+        DynamicObject {
+            shape,
+            pose,
+            trajectory,
+            in_ground_truth: false,
+            next_waypoint: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // move along the trajectory, blocked(x, y) reports whether a position is inside the static map
+    pub fn step(&mut self, time_step: f32, blocked: impl Fn(f32, f32) -> bool) {
+        match &self.trajectory {
+            Trajectory::Stationary => (),
+            Trajectory::Waypoints {
+                points,
+                speed,
+                looped,
+            } => {
+                let mut travel = speed * time_step;
+                // distance covered since the last return to the first waypoint, a looped path of
+                // zero length would otherwise go round forever
+                let mut lap_distance = 0.;
+                while travel > 0. && self.next_waypoint < points.len() {
+                    let target = points[self.next_waypoint];
+                    let dx = target[0] - self.pose.x;
+                    let dy = target[1] - self.pose.y;
+                    let dist = (dx * dx + dy * dy).sqrt();
+                    if dist > travel {
+                        self.pose.x += dx / dist * travel;
+                        self.pose.y += dy / dist * travel;
+                        self.pose.theta = dy.atan2(dx);
+                        travel = 0.;
+                    } else {
+                        self.pose.x = target[0];
+                        self.pose.y = target[1];
+                        travel -= dist;
+                        lap_distance += dist;
+                        self.next_waypoint += 1;
+                        if *looped && self.next_waypoint == points.len() {
+                            self.next_waypoint = 0;
+                            if lap_distance == 0. {
+                                break;
+                            }
+                            lap_distance = 0.;
+                        }
+                    }
+                }
+            }
+            Trajectory::RandomWalk {
+                speed,
+                turn_std_dev,
+            } => {
+                let turn = Normal::new(0., turn_std_dev * time_step.sqrt()).unwrap();
+                self.pose.theta += turn.sample(&mut self.rng);
+                let x = self.pose.x + speed * time_step * self.pose.theta.cos();
+                let y = self.pose.y + speed * time_step * self.pose.theta.sin();
+                if blocked(x, y) {
+                    self.pose.theta += std::f32::consts::PI;
+                } else {
+                    self.pose.x = x;
+                    self.pose.y = y;
+                }
+            }
+        }
+    }
+
+    // whether a world point is inside the object
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let (local_x, local_y) = self.to_local(x, y);
+        match self.shape {
+            Shape::Circle { radius } => local_x * local_x + local_y * local_y <= radius * radius,
+            Shape::Box {
+                half_length,
+                half_width,
+            } => local_x.abs() <= half_length && local_y.abs() <= half_width,
+        }
+    }
+
+    // radius of a circle about the pose enclosing the object
+    pub fn bounding_radius(&self) -> f32 {
+        match self.shape {
+            Shape::Circle { radius } => radius,
+            Shape::Box {
+                half_length,
+                half_width,
+            } => (half_length * half_length + half_width * half_width).sqrt(),
+        }
+    }
+
+    // distance along the ray to the object outline, zero if the ray starts inside
+    pub fn intersect(&self, ray: Pose) -> Option<f32> {
+        let (origin_x, origin_y) = self.to_local(ray.x, ray.y);
+        let angle = ray.theta - self.pose.theta;
+        let (dir_y, dir_x) = angle.sin_cos();
+        match self.shape {
+            Shape::Circle { radius } => {
+                // solve |origin + t * dir|^2 = radius^2 for the smallest t >= 0
+                let b = origin_x * dir_x + origin_y * dir_y;
+                let c = origin_x * origin_x + origin_y * origin_y - radius * radius;
+                if c <= 0. {
+                    return Some(0.);
+                }
+                let discriminant = b * b - c;
+                if b > 0. || discriminant < 0. {
+                    return None;
+                }
+                Some(-b - discriminant.sqrt())
+            }
+            Shape::Box {
+                half_length,
+                half_width,
+            } => {
+                // slab method in the box frame
+                let mut t_enter = 0f32;
+                let mut t_exit = f32::INFINITY;
+                for (origin, dir, half) in [
+                    (origin_x, dir_x, half_length),
+                    (origin_y, dir_y, half_width),
+                ]
+                .iter()
+                {
+                    if dir.abs() < 1e-12 {
+                        if origin.abs() > *half {
+                            return None;
+                        }
+                    } else {
+                        let t_a = (-half - origin) / dir;
+                        let t_b = (half - origin) / dir;
+                        t_enter = t_enter.max(t_a.min(t_b));
+                        t_exit = t_exit.min(t_a.max(t_b));
+                    }
+                }
+                if t_enter <= t_exit {
+                    Some(t_enter)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn to_local(&self, x: f32, y: f32) -> (f32, f32) {
+        let (s, c) = self.pose.theta.sin_cos();
+        let dx = x - self.pose.x;
+        let dy = y - self.pose.y;
+        (c * dx + s * dy, -s * dx + c * dy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn follower(points: Vec<[f32; 2]>, looped: bool) -> DynamicObject {
+        DynamicObject::new(
+            Shape::Circle { radius: 0.2 },
+            Pose::default(),
+            Trajectory::Waypoints {
+                points,
+                speed: 1.,
+                looped,
+            },
+            0,
+        )
+    }
+
+    #[test]
+    fn looped_single_waypoint_stops_there() {
+        let mut object = follower(vec![[1., 0.]], true);
+        for _ in 0..4 {
+            object.step(0.5, |_, _| false);
+        }
+        assert_abs_diff_eq!(object.pose.x, 1.);
+        assert_abs_diff_eq!(object.pose.y, 0.);
+    }
+
+    #[test]
+    fn looped_path_of_coincident_waypoints_stops_there() {
+        let mut object = follower(vec![[0., 1.]; 3], true);
+        for _ in 0..4 {
+            object.step(0.5, |_, _| false);
+        }
+        assert_abs_diff_eq!(object.pose.x, 0.);
+        assert_abs_diff_eq!(object.pose.y, 1.);
+
+        // already at the only waypoint
+        let mut object = follower(vec![[0., 0.]], true);
+        object.step(0.5, |_, _| false);
+        assert_abs_diff_eq!(object.pose.x, 0.);
+    }
+
+    #[test]
+    fn looped_square_wraps_round() {
+        let square = vec![[1., 0.], [1., 1.], [0., 1.], [0., 0.]];
+        let mut object = follower(square.clone(), true);
+        // one and a half laps in one step
+        object.step(6., |_, _| false);
+        assert_abs_diff_eq!(object.pose.x, 1., epsilon = 1e-6);
+        assert_abs_diff_eq!(object.pose.y, 1., epsilon = 1e-6);
+
+        let mut object = follower(square, false);
+        object.step(6., |_, _| false);
+        assert_abs_diff_eq!(object.pose.x, 0.);
+        assert_abs_diff_eq!(object.pose.y, 0.);
+    }
+}
@@ -0,0 +1,229 @@
+// Point landmarks such as poles or reflectors, observed as a range and bearing from the robot
+use super::{Environment, PixelCoord, RayCast};
+use crate::diff_drive::{normalise_angle, Pose};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use std::f32::consts::PI;
+
+impl Environment {
+    // place count landmarks at random in free cells of the static grid
+    pub fn scatter_landmarks(&mut self, count: usize, seed: u64) {
+        let free: Vec<PixelCoord> = self
+            .grid
+            .indexed_iter()
+            .filter(|(_, occupied)| !**occupied)
+            .map(|((y, x), _)| PixelCoord { x, y })
+            .collect();
+        if free.is_empty() {
+            return;
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..count {
+            let (x, y) = self.grid_to_world(free[rng.gen_range(0..free.len())]);
+            self.landmarks.push([x, y]);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct LandmarkObservation {
+    pub range: f32,
+    pub bearing: f32, // relative to the robot heading
+    pub id: usize,    // index into Environment::landmarks, for evaluation only
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct LandmarkSensorConfig {
+    pub max_range: f32,
+    pub fov: f32, // radians, centred on the robot heading
+    pub range_std_dev: f32,
+    pub bearing_std_dev: f32,
+    pub seed: Option<u64>, // None seeds from entropy
+}
+
+impl Default for LandmarkSensorConfig {
+    fn default() -> Self {
+        LandmarkSensorConfig {
+            max_range: 4.,
+            fov: 2. * PI,
+            range_std_dev: 0.05,
+            bearing_std_dev: 0.02,
+            seed: None,
+        }
+    }
+}
+
+// Sees the landmarks within range and field of view that the map and dynamic objects don't hide
+pub struct LandmarkSensor {
+    pub config: LandmarkSensorConfig,
+    rng: StdRng,
+}
+
+impl LandmarkSensor {
+    pub fn new(config: LandmarkSensorConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        LandmarkSensor { config, rng }
+    }
+
+    pub fn observe(
+        &mut self,
+        robot_pose: Pose,
+        environment: &Environment,
+    ) -> Vec<LandmarkObservation> {
+        let mut observations = vec![];
+        for (id, [x, y]) in environment.landmarks.iter().enumerate() {
+            let dx = x - robot_pose.x;
+            let dy = y - robot_pose.y;
+            let range = (dx * dx + dy * dy).sqrt();
+            let bearing = normalise_angle(dy.atan2(dx) - robot_pose.theta);
+            if range > self.config.max_range || bearing.abs() > self.config.fov / 2. {
+                continue;
+            }
+            let ray = Pose {
+                theta: robot_pose.theta + bearing,
+                ..robot_pose
+            };
+            let occluded = matches!(
+                environment.cast_ray(ray, range),
+                Some(hit) if hit < range - environment.geometry.resolution
+            );
+            if occluded {
+                continue;
+            }
+            observations.push(LandmarkObservation {
+                range: (range + self.noise(self.config.range_std_dev)).max(0.),
+                bearing: normalise_angle(bearing + self.noise(self.config.bearing_std_dev)),
+                id,
+            });
+        }
+        observations
+    }
+
+    fn noise(&mut self, std_dev: f32) -> f32 {
+        if std_dev > 0. {
+            Normal::new(0., std_dev).unwrap().sample(&mut self.rng)
+        } else {
+            0.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::dynamic::{DynamicObject, Shape, Trajectory};
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use ndarray::Array2;
+
+    // 4 x 4 m room with 10 cm walls, and a 10 cm thick wall from the top down to the middle
+    fn room() -> Environment {
+        let mut grid = Array2::from_elem((40, 40), false);
+        for ((row, col), cell) in grid.indexed_iter_mut() {
+            let wall = !(1..39).contains(&row) || !(1..39).contains(&col);
+            *cell = wall || col == 20 && row < 20;
+        }
+        Environment::centred(grid, 0.1)
+    }
+
+    fn sensor() -> LandmarkSensor {
+        LandmarkSensor::new(LandmarkSensorConfig {
+            range_std_dev: 0.,
+            bearing_std_dev: 0.,
+            seed: Some(0),
+            ..LandmarkSensorConfig::default()
+        })
+    }
+
+    fn ids(observations: &[LandmarkObservation]) -> Vec<usize> {
+        observations.iter().map(|o| o.id).collect()
+    }
+
+    #[test]
+    fn scatter_landmarks_fills_free_cells_reproducibly() {
+        let mut environment = room();
+        environment.scatter_landmarks(200, 1);
+        assert_eq!(environment.landmarks.len(), 200);
+        for &[x, y] in &environment.landmarks {
+            assert!(!environment.is_blocked(x, y), "landmark at {}, {}", x, y);
+        }
+
+        let mut again = room();
+        again.scatter_landmarks(200, 1);
+        assert_eq!(again.landmarks, environment.landmarks);
+        let mut other_seed = room();
+        other_seed.scatter_landmarks(200, 2);
+        assert_ne!(other_seed.landmarks, environment.landmarks);
+
+        let mut solid = Environment::centred(Array2::from_elem((10, 10), true), 0.1);
+        solid.scatter_landmarks(5, 1);
+        assert!(solid.landmarks.is_empty());
+    }
+
+    #[test]
+    fn observe_reports_range_and_bearing() {
+        let mut environment = room();
+        environment.landmarks = vec![[1., -1.]];
+        let pose = Pose {
+            x: 1.,
+            y: 0.,
+            theta: PI / 2.,
+        };
+        let observations = sensor().observe(pose, &environment);
+        assert_eq!(ids(&observations), [0]);
+        assert_abs_diff_eq!(observations[0].range, 1., epsilon = 1e-6);
+        assert_abs_diff_eq!(observations[0].bearing.abs(), PI, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn observe_skips_landmarks_out_of_range_or_view() {
+        let mut environment = room();
+        environment.landmarks = vec![[0.5, -1.], [-1.5, -1.], [1.5, -1.]];
+        let mut sensor = LandmarkSensor::new(LandmarkSensorConfig {
+            max_range: 1.5,
+            fov: PI,
+            ..sensor().config
+        });
+        // facing right from below the wall, so the second landmark is behind the sensor
+        let pose = Pose {
+            x: 0.,
+            y: -1.,
+            theta: 0.,
+        };
+        assert_eq!(ids(&sensor.observe(pose, &environment)), [0, 2]);
+        sensor.config.fov = 2. * PI;
+        sensor.config.max_range = 1.;
+        assert_eq!(ids(&sensor.observe(pose, &environment)), [0]);
+    }
+
+    #[test]
+    fn observe_skips_landmarks_hidden_by_walls_and_objects() {
+        let mut environment = room();
+        // either side of the wall, and one on the near side beyond a box
+        environment.landmarks = vec![[-1., 1.], [1., 1.], [-1., -1.]];
+        let pose = Pose {
+            x: -1.,
+            y: 0.5,
+            theta: 0.,
+        };
+        assert_eq!(ids(&sensor().observe(pose, &environment)), [0, 2]);
+
+        environment.dynamic_objects.push(DynamicObject::new(
+            Shape::Circle { radius: 0.2 },
+            Pose {
+                x: -1.,
+                y: -0.3,
+                theta: 0.,
+            },
+            Trajectory::Stationary,
+            0,
+        ));
+        assert_eq!(ids(&sensor().observe(pose, &environment)), [0]);
+        // a landmark standing against a wall is still seen
+        environment.landmarks = vec![[-0.05, 1.]];
+        assert_eq!(ids(&sensor().observe(pose, &environment)), [0]);
+    }
+}
@@ -0,0 +1,220 @@
+// Loading environments from map images, either ROS map_server maps (YAML + image) or plain
+// images thresholded on pixel colour
+use super::Environment;
+use crate::diff_drive::Pose;
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(std::io::Error),
+    Yaml(serde_yaml::Error),
+    Image(image::ImageError),
+    Json(serde_json::Error),
+    Parse { line: usize, message: String },
+    InvalidParameter(String),
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapLoadError::Io(e) => write!(f, "could not access map file: {}", e),
+            MapLoadError::Yaml(e) => write!(f, "could not parse map yaml: {}", e),
+            MapLoadError::Image(e) => write!(f, "could not decode or encode map image: {}", e),
+            MapLoadError::Json(e) => write!(f, "could not parse map json: {}", e),
+            MapLoadError::Parse { line, message } => {
+                write!(f, "could not parse map line {}: {}", line, message)
+            }
+            MapLoadError::InvalidParameter(msg) => write!(f, "invalid map parameter: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MapLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MapLoadError::Io(e) => Some(e),
+            MapLoadError::Yaml(e) => Some(e),
+            MapLoadError::Image(e) => Some(e),
+            MapLoadError::Json(e) => Some(e),
+            MapLoadError::Parse { .. } | MapLoadError::InvalidParameter(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MapLoadError {
+    fn from(e: std::io::Error) -> Self {
+        MapLoadError::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for MapLoadError {
+    fn from(e: serde_yaml::Error) -> Self {
+        MapLoadError::Yaml(e)
+    }
+}
+
+impl From<serde_json::Error> for MapLoadError {
+    fn from(e: serde_json::Error) -> Self {
+        MapLoadError::Json(e)
+    }
+}
+
+impl From<image::ImageError> for MapLoadError {
+    fn from(e: image::ImageError) -> Self {
+        MapLoadError::Image(e)
+    }
+}
+
+// How pixel colours are turned into occupied cells
+#[derive(Copy, Clone, Debug)]
+pub enum OccupancyRule {
+    // ROS map_server trinary interpretation of the mean channel value x: occupancy probability is
+    // (255 - x) / 255, or x / 255 when negated. Cells between the thresholds are unknown and,
+    // like free cells, do not block rays.
+    Ros {
+        occupied_thresh: f32,
+        free_thresh: f32,
+        negate: bool,
+    },
+    // occupied when every RGB channel is below the value, e.g. dark walls on a light floor plan
+    DarkerThan(u8),
+}
+
+impl OccupancyRule {
+    fn is_occupied(&self, pixel: &image::Rgb<u8>) -> bool {
+        match *self {
+            OccupancyRule::Ros {
+                occupied_thresh,
+                negate,
+                ..
+            } => {
+                let mean = pixel.0.iter().map(|c| *c as f32).sum::<f32>() / 3.;
+                ros_occupancy(mean, negate) > occupied_thresh
+            }
+            OccupancyRule::DarkerThan(value) => pixel.0.iter().all(|c| *c < value),
+        }
+    }
+
+    fn validate(&self) -> Result<(), MapLoadError> {
+        if let OccupancyRule::Ros {
+            occupied_thresh,
+            free_thresh,
+            ..
+        } = *self
+        {
+            let in_range = |t: f32| (0. ..=1.).contains(&t);
+            if !in_range(occupied_thresh) || !in_range(free_thresh) {
+                return Err(MapLoadError::InvalidParameter(
+                    "thresholds must be between 0 and 1".to_string(),
+                ));
+            }
+            if free_thresh > occupied_thresh {
+                return Err(MapLoadError::InvalidParameter(format!(
+                    "free_thresh {} is above occupied_thresh {}",
+                    free_thresh, occupied_thresh
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// occupancy probability of a map_server pixel from its mean channel value
+pub(crate) fn ros_occupancy(value: f32, negate: bool) -> f32 {
+    if negate {
+        value / 255.
+    } else {
+        (255. - value) / 255.
+    }
+}
+
+// Contents of a ROS map_server map.yaml file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MapMetadata {
+    pub image: PathBuf, // relative to the yaml file unless absolute
+    pub resolution: f32,
+    pub origin: [f32; 3], // x, y, yaw of the bottom left pixel
+    pub negate: u8,
+    pub occupied_thresh: f32,
+    pub free_thresh: f32,
+}
+
+impl MapMetadata {
+    // read and check a yaml file, shared by the loaders for each kind of grid
+    pub fn load(yaml_path: &Path) -> Result<Self, MapLoadError> {
+        let metadata: MapMetadata = serde_yaml::from_reader(File::open(yaml_path)?)?;
+        metadata.occupancy_rule().validate()?;
+        validate_resolution(metadata.resolution)?;
+        Ok(metadata)
+    }
+
+    pub fn occupancy_rule(&self) -> OccupancyRule {
+        OccupancyRule::Ros {
+            occupied_thresh: self.occupied_thresh,
+            free_thresh: self.free_thresh,
+            negate: self.negate != 0,
+        }
+    }
+
+    pub fn origin_pose(&self) -> Pose {
+        Pose {
+            x: self.origin[0],
+            y: self.origin[1],
+            theta: self.origin[2],
+        }
+    }
+
+    // path of the map image given the path of the yaml file it was read from
+    pub fn image_path(&self, yaml_path: &Path) -> PathBuf {
+        match yaml_path.parent() {
+            Some(dir) if self.image.is_relative() => dir.join(&self.image),
+            _ => self.image.clone(),
+        }
+    }
+}
+
+// load a map saved by ROS map_server / map_saver from its yaml file
+pub fn load_ros_map(yaml_path: &Path) -> Result<Environment, MapLoadError> {
+    let metadata = MapMetadata::load(yaml_path)?;
+    load_image_map(
+        &metadata.image_path(yaml_path),
+        metadata.occupancy_rule(),
+        metadata.resolution,
+        Some(metadata.origin_pose()),
+    )
+}
+
+// load a map image with an explicit occupancy rule, a None origin centres the map on world (0, 0)
+pub fn load_image_map(
+    image_path: &Path,
+    rule: OccupancyRule,
+    resolution: f32,
+    origin: Option<Pose>,
+) -> Result<Environment, MapLoadError> {
+    rule.validate()?;
+    validate_resolution(resolution)?;
+
+    let img = image::open(image_path)?.to_rgb8();
+    let occupied = img.pixels().map(|pixel| rule.is_occupied(pixel)).collect();
+    let grid = Array2::from_shape_vec((img.height() as usize, img.width() as usize), occupied)
+        .expect("image buffer size matches its dimensions");
+
+    Ok(match origin {
+        Some(origin) => Environment::new(grid, resolution, origin),
+        None => Environment::centred(grid, resolution),
+    })
+}
+
+fn validate_resolution(resolution: f32) -> Result<(), MapLoadError> {
+    if resolution.is_nan() || resolution <= 0. {
+        return Err(MapLoadError::InvalidParameter(format!(
+            "resolution must be positive, got {}",
+            resolution
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,376 @@
+// Environments made of line segments, for exact ray casting without rasterisation error
+use super::map::MapLoadError;
+use super::{traversal_params, RayCast};
+use crate::diff_drive::Pose;
+use serde::Deserialize;
+use std::path::Path;
+
+// wall from start to end, in metres
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct Segment {
+    pub start: [f32; 2],
+    pub end: [f32; 2],
+}
+
+impl Segment {
+    pub fn new(start: [f32; 2], end: [f32; 2]) -> Self {
+        Segment { start, end }
+    }
+
+    // distance along the ray to the segment, parallel segments are never hit
+    pub fn intersect(&self, ray: Pose) -> Option<f32> {
+        let (dir_y, dir_x) = ray.theta.sin_cos();
+        let edge_x = self.end[0] - self.start[0];
+        let edge_y = self.end[1] - self.start[1];
+        let denom = cross(dir_x, dir_y, edge_x, edge_y);
+        if denom.abs() < 1e-9 {
+            return None;
+        }
+        let to_start_x = self.start[0] - ray.x;
+        let to_start_y = self.start[1] - ray.y;
+        let t = cross(to_start_x, to_start_y, edge_x, edge_y) / denom;
+        let u = cross(to_start_x, to_start_y, dir_x, dir_y) / denom;
+        if t >= 0. && (0. ..=1.).contains(&u) {
+            Some(t)
+        } else {
+            None
+        }
+    }
+}
+
+fn cross(ax: f32, ay: f32, bx: f32, by: f32) -> f32 {
+    ax * by - ay * bx
+}
+
+// segments of a closed polygon, the last vertex is joined back to the first. Fewer than 3 vertices
+// do not enclose anything and give no segments, the loaders reject such polygons instead.
+pub fn polygon_segments(vertices: &[[f32; 2]]) -> Vec<Segment> {
+    if vertices.len() < 3 {
+        return vec![];
+    }
+    vertices
+        .iter()
+        .zip(vertices.iter().cycle().skip(1))
+        .map(|(start, end)| Segment::new(*start, *end))
+        .collect()
+}
+
+// Line segment environment. Segments are bucketed into a uniform grid so a ray only tests the
+// segments in the cells it passes through, found with the same traversal as the raster Environment.
+#[derive(Clone, Debug)]
+pub struct VectorEnvironment {
+    pub segments: Vec<Segment>,
+    min: [f32; 2],
+    cell_size: f32,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>, // segment indices, row major from min
+}
+
+impl VectorEnvironment {
+    pub fn new(segments: Vec<Segment>) -> Self {
+        let mut min = [f32::INFINITY; 2];
+        let mut max = [f32::NEG_INFINITY; 2];
+        for segment in &segments {
+            for point in [segment.start, segment.end].iter() {
+                for axis in 0..2 {
+                    min[axis] = min[axis].min(point[axis]);
+                    max[axis] = max[axis].max(point[axis]);
+                }
+            }
+        }
+        if segments.is_empty() {
+            min = [0.; 2];
+            max = [0.; 2];
+        }
+
+        // aim for a few segments per cell, pad so segments on the boundary are inside
+        let extent = (max[0] - min[0]).max(max[1] - min[1]).max(1e-3);
+        let cells_per_side = ((segments.len() as f32).sqrt().ceil() as usize).clamp(1, 256);
+        let cell_size = extent / cells_per_side as f32 * 1.001;
+        let cols = ((max[0] - min[0]) / cell_size).floor() as usize + 1;
+        let rows = ((max[1] - min[1]) / cell_size).floor() as usize + 1;
+
+        let mut environment = VectorEnvironment {
+            segments,
+            min,
+            cell_size,
+            cols,
+            rows,
+            cells: vec![vec![]; cols * rows],
+        };
+        for (index, segment) in environment.segments.iter().enumerate() {
+            let (col_a, row_a) = environment.cell_of(segment.start);
+            let (col_b, row_b) = environment.cell_of(segment.end);
+            for row in row_a.min(row_b)..=row_a.max(row_b) {
+                for col in col_a.min(col_b)..=col_a.max(col_b) {
+                    environment.cells[row * cols + col].push(index);
+                }
+            }
+        }
+        environment
+    }
+
+    pub fn from_polygons(polygons: &[Vec<[f32; 2]>]) -> Self {
+        VectorEnvironment::new(polygons.iter().flat_map(|p| polygon_segments(p)).collect())
+    }
+
+    fn cell_of(&self, point: [f32; 2]) -> (usize, usize) {
+        let col = ((point[0] - self.min[0]) / self.cell_size).floor() as usize;
+        let row = ((point[1] - self.min[1]) / self.cell_size).floor() as usize;
+        (col.min(self.cols - 1), row.min(self.rows - 1))
+    }
+
+    // distance along the ray to where it enters the grid bounds, if it does (slab method)
+    fn entry_distance(&self, ray: Pose) -> Option<f32> {
+        let (dir_y, dir_x) = ray.theta.sin_cos();
+        let origin = [ray.x, ray.y];
+        let dir = [dir_x, dir_y];
+        let extent = [
+            self.cols as f32 * self.cell_size,
+            self.rows as f32 * self.cell_size,
+        ];
+        let mut t_enter = 0f32;
+        let mut t_exit = f32::INFINITY;
+        for axis in 0..2 {
+            let low = self.min[axis];
+            let high = self.min[axis] + extent[axis];
+            if dir[axis].abs() < 1e-12 {
+                if origin[axis] < low || origin[axis] >= high {
+                    return None;
+                }
+            } else {
+                let t_a = (low - origin[axis]) / dir[axis];
+                let t_b = (high - origin[axis]) / dir[axis];
+                t_enter = t_enter.max(t_a.min(t_b));
+                t_exit = t_exit.min(t_a.max(t_b));
+            }
+        }
+        if t_enter < t_exit {
+            Some(t_enter)
+        } else {
+            None
+        }
+    }
+}
+
+impl RayCast for VectorEnvironment {
+    fn cast_ray(&self, ray: Pose, max_range: f32) -> Option<f32> {
+        let t_entry = self.entry_distance(ray)?;
+        if t_entry > max_range {
+            return None;
+        }
+        let (dir_y, dir_x) = ray.theta.sin_cos();
+        // grid frame: cells right and up from min, one unit per cell
+        let start_x = (ray.x + t_entry * dir_x - self.min[0]) / self.cell_size;
+        let start_y = (ray.y + t_entry * dir_y - self.min[1]) / self.cell_size;
+
+        // clamp since the entry point can lie exactly on the far edge of the grid
+        let mut col = (start_x.floor() as isize).clamp(0, self.cols as isize - 1);
+        let mut row = (start_y.floor() as isize).clamp(0, self.rows as isize - 1);
+        let (step_x, mut t_max_x, t_delta_x) = traversal_params(start_x, col, dir_x);
+        let (step_y, mut t_max_y, t_delta_y) = traversal_params(start_y, row, dir_y);
+
+        loop {
+            // a segment can span several cells, only accept hits inside the current one so a
+            // nearer segment in a later cell is not skipped
+            let t_cell_exit = t_entry + t_max_x.min(t_max_y) * self.cell_size;
+            let nearest = self.cells[row as usize * self.cols + col as usize]
+                .iter()
+                .filter_map(|index| self.segments[*index].intersect(ray))
+                .filter(|t| *t <= t_cell_exit + 1e-5)
+                .fold(None, |nearest: Option<f32>, t| {
+                    Some(nearest.map_or(t, |n| n.min(t)))
+                });
+            if let Some(t) = nearest {
+                return if t <= max_range { Some(t) } else { None };
+            }
+            if t_cell_exit > max_range {
+                return None;
+            }
+            if t_max_x < t_max_y {
+                t_max_x += t_delta_x;
+                col += step_x;
+            } else {
+                t_max_y += t_delta_y;
+                row += step_y;
+            }
+            if col < 0 || col >= self.cols as isize || row < 0 || row >= self.rows as isize {
+                return None;
+            }
+        }
+    }
+}
+
+// JSON layout:
+//   {"segments": [{"start": [x, y], "end": [x, y]}, ...], "polygons": [[[x, y], ...], ...]}
+#[derive(Deserialize)]
+struct VectorMapFile {
+    #[serde(default)]
+    segments: Vec<Segment>,
+    #[serde(default)]
+    polygons: Vec<Vec<[f32; 2]>>,
+}
+
+pub fn load_json(path: &Path) -> Result<VectorEnvironment, MapLoadError> {
+    parse_json(&std::fs::read_to_string(path)?)
+}
+
+pub fn parse_json(text: &str) -> Result<VectorEnvironment, MapLoadError> {
+    let file: VectorMapFile = serde_json::from_str(text)?;
+    let mut segments = file.segments;
+    for (index, polygon) in file.polygons.iter().enumerate() {
+        if polygon.len() < 3 {
+            return Err(MapLoadError::InvalidParameter(format!(
+                "polygon {} has {} vertices, at least 3 are needed",
+                index,
+                polygon.len()
+            )));
+        }
+        segments.extend(polygon_segments(polygon));
+    }
+    Ok(VectorEnvironment::new(segments))
+}
+
+// Text layout, one shape per line, blank lines and lines starting with # are ignored:
+//   segment x1 y1 x2 y2
+//   polygon x1 y1 x2 y2 x3 y3 ...
+pub fn load_text(path: &Path) -> Result<VectorEnvironment, MapLoadError> {
+    parse_text(&std::fs::read_to_string(path)?)
+}
+
+pub fn parse_text(text: &str) -> Result<VectorEnvironment, MapLoadError> {
+    let mut segments = vec![];
+    for (line_number, line) in text.lines().enumerate() {
+        let parse_error = |message: String| MapLoadError::Parse {
+            line: line_number + 1,
+            message,
+        };
+        let mut words = line.split_whitespace();
+        let kind = match words.next() {
+            None => continue,
+            Some(word) if word.starts_with('#') => continue,
+            Some(word) => word,
+        };
+        let values = words
+            .map(|word| word.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| parse_error(e.to_string()))?;
+        let valid_count = match kind {
+            "segment" => values.len() == 4,
+            "polygon" => values.len() >= 6 && values.len() % 2 == 0,
+            other => return Err(parse_error(format!("unknown shape '{}'", other))),
+        };
+        if !valid_count {
+            return Err(parse_error(format!(
+                "wrong number of coordinates for {}: {}",
+                kind,
+                values.len()
+            )));
+        }
+        let points: Vec<[f32; 2]> = values.chunks(2).map(|pair| [pair[0], pair[1]]).collect();
+        if kind == "segment" {
+            segments.push(Segment::new(points[0], points[1]));
+        } else {
+            segments.extend(polygon_segments(&points));
+        }
+    }
+    Ok(VectorEnvironment::new(segments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn brute_force(segments: &[Segment], ray: Pose, max_range: f32) -> Option<f32> {
+        segments
+            .iter()
+            .filter_map(|segment| segment.intersect(ray))
+            .filter(|t| *t <= max_range)
+            .fold(None, |nearest: Option<f32>, t| {
+                Some(nearest.map_or(t, |n| n.min(t)))
+            })
+    }
+
+    fn assert_matches_brute_force(environment: &VectorEnvironment, rng: &mut StdRng) {
+        for _ in 0..2000 {
+            let ray = Pose {
+                x: rng.gen_range(-6.0..6.),
+                y: rng.gen_range(-6.0..6.),
+                theta: rng.gen_range(-std::f32::consts::PI..std::f32::consts::PI),
+            };
+            let max_range = rng.gen_range(0.5..15.);
+            let expected = brute_force(&environment.segments, ray, max_range);
+            let actual = environment.cast_ray(ray, max_range);
+            match (expected, actual) {
+                (Some(expected), Some(actual)) => {
+                    assert!((expected - actual).abs() < 1e-4, "{:?}", ray)
+                }
+                (None, None) => {}
+                _ => panic!("{:?}: expected {:?}, got {:?}", ray, expected, actual),
+            }
+        }
+    }
+
+    #[test]
+    fn polygon_segments_closes_the_polygon() {
+        let square = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+        let segments = polygon_segments(&square);
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[3], Segment::new([0., 1.], [0., 0.]));
+    }
+
+    #[test]
+    fn polygon_segments_ignores_degenerate_polygons() {
+        assert!(polygon_segments(&[]).is_empty());
+        assert!(polygon_segments(&[[0., 0.]]).is_empty());
+        assert!(polygon_segments(&[[0., 0.], [1., 0.]]).is_empty());
+    }
+
+    #[test]
+    fn json_polygon_needs_three_vertices() {
+        let text = r#"{"polygons": [[[0, 0], [1, 0], [1, 1]], [[0, 0], [1, 0]]]}"#;
+        assert!(matches!(
+            parse_json(text),
+            Err(MapLoadError::InvalidParameter(_))
+        ));
+        let text = r#"{"polygons": [[[0, 0], [1, 0], [1, 1]]]}"#;
+        assert_eq!(parse_json(text).unwrap().segments.len(), 3);
+    }
+
+    #[test]
+    fn random_segments_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let segments = (0..200)
+            .map(|_| {
+                let start = [rng.gen_range(-5.0..5.), rng.gen_range(-5.0..5.)];
+                let end = [
+                    start[0] + rng.gen_range(-1.0..1.),
+                    start[1] + rng.gen_range(-1.0..1.),
+                ];
+                Segment::new(start, end)
+            })
+            .collect();
+        assert_matches_brute_force(&VectorEnvironment::new(segments), &mut rng);
+    }
+
+    #[test]
+    fn long_walls_match_brute_force() {
+        // walls spanning many grid cells, so hits in later cells must not shadow nearer ones
+        let mut polygons = vec![vec![[-5., -5.], [5., -5.], [5., 5.], [-5., 5.]]];
+        polygons.extend((0..8).map(|i| {
+            let x = -4. + i as f32;
+            vec![[x, -3.], [x + 0.3, -3.], [x + 0.3, 3.5]]
+        }));
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_matches_brute_force(&VectorEnvironment::from_polygons(&polygons), &mut rng);
+    }
+
+    #[test]
+    fn empty_environment_is_never_hit() {
+        let environment = VectorEnvironment::new(vec![]);
+        assert_eq!(environment.cast_ray(Pose::default(), 10.), None);
+    }
+}
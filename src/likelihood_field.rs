@@ -0,0 +1,123 @@
+// Likelihood field sensor model from Probabilistic Robotics by S. Thrun et al. (ch. 6.4): a scan
+// end point is likely when it lies close to an obstacle in the map, regardless of what the beam
+// passed through on the way
+use crate::diff_drive::Pose;
+use crate::distance_transform::distance_transform;
+use crate::laser_scan::LaserScan;
+use crate::lidar::{Environment, GridGeometry};
+use crate::occupancy_grid::OccupancyGrid;
+use ndarray::Array2;
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone, Debug)]
+pub struct LikelihoodFieldConfig {
+    pub sigma_hit: f32,    // std dev of end points about the nearest obstacle, metres
+    pub z_hit: f32,        // weight of the Gaussian about the nearest obstacle
+    pub z_rand: f32,       // weight of uniformly random readings
+    pub max_distance: f32, // distances are capped here, and used for end points off the map
+}
+
+impl Default for LikelihoodFieldConfig {
+    fn default() -> Self {
+        LikelihoodFieldConfig {
+            sigma_hit: 0.2,
+            z_hit: 0.95,
+            z_rand: 0.05,
+            max_distance: 2.,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LikelihoodField {
+    pub geometry: GridGeometry,
+    pub config: LikelihoodFieldConfig,
+    pub distances: Array2<f32>, // metres from each cell centre to the nearest occupied cell centre
+}
+
+impl LikelihoodField {
+    // field of the static grid of an environment
+    pub fn new(environment: &Environment, config: LikelihoodFieldConfig) -> Self {
+        Self::from_occupied(environment.geometry, &environment.grid, config)
+    }
+
+    // field of the cells of a built map that are above its occupied threshold
+    pub fn from_occupancy_grid(grid: &OccupancyGrid, config: LikelihoodFieldConfig) -> Self {
+        Self::from_occupied(grid.geometry, &grid.occupied(), config)
+    }
+
+    pub fn from_occupied(
+        geometry: GridGeometry,
+        occupied: &Array2<bool>,
+        config: LikelihoodFieldConfig,
+    ) -> Self {
+        let distances =
+            distance_transform(occupied, geometry.resolution).mapv(|d| d.min(config.max_distance));
+        LikelihoodField {
+            geometry,
+            config,
+            distances,
+        }
+    }
+
+    // distance from a world point to the nearest obstacle, max_distance outside the map
+    pub fn distance(&self, x: f32, y: f32) -> f32 {
+        match self.geometry.world_to_grid(x, y) {
+            Some(coord) => self.distances[[coord.y, coord.x]],
+            None => self.config.max_distance,
+        }
+    }
+
+    // log likelihood of every step-th valid return of a scan taken from the given sensor pose
+    pub fn log_likelihood(&self, scan: &LaserScan, sensor_pose: Pose, step: usize) -> f64 {
+        let LikelihoodFieldConfig {
+            sigma_hit,
+            z_hit,
+            z_rand,
+            ..
+        } = self.config;
+        let normaliser = 1. / (sigma_hit * (2. * PI).sqrt());
+        let random = z_rand / scan.range_max;
+        let mut total = 0.;
+        for (i, range) in scan.ranges.iter().enumerate().step_by(step.max(1)) {
+            if !scan.is_valid(*range) {
+                continue;
+            }
+            let angle = sensor_pose.theta + scan.angle(i);
+            let distance = self.distance(
+                sensor_pose.x + range * angle.cos(),
+                sensor_pose.y + range * angle.sin(),
+            );
+            let z = distance / sigma_hit;
+            let p = z_hit * normaliser * (-0.5 * z * z).exp() + random;
+            total += (p as f64).ln();
+        }
+        total
+    }
+
+    // Fraction of the valid returns that land on the map's obstacles, each return counting
+    // exp(-d^2 / 2 sigma_hit^2) for its distance d to the nearest one. 1 is a perfect match,
+    // useful for comparing candidate poses when matching a scan to the map.
+    pub fn score(&self, scan: &LaserScan, sensor_pose: Pose) -> f32 {
+        let sigma_hit = self.config.sigma_hit;
+        let mut total = 0.;
+        let mut count = 0;
+        for (i, range) in scan.ranges.iter().enumerate() {
+            if !scan.is_valid(*range) {
+                continue;
+            }
+            let angle = sensor_pose.theta + scan.angle(i);
+            let distance = self.distance(
+                sensor_pose.x + range * angle.cos(),
+                sensor_pose.y + range * angle.sin(),
+            );
+            total += (-0.5 * (distance / sigma_hit).powi(2)).exp();
+            count += 1;
+        }
+        if count == 0 {
+            0.
+        } else {
+            total / count as f32
+        }
+    }
+}
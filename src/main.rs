@@ -1,20 +1,23 @@
-use cram::{diff_drive, lidar, draw, pose_graph};
-use nannou::image::io::Reader as ImageReader;
+use cram::{diff_drive, draw, laser_scan, lidar, mcl, pose_graph};
 use nannou::prelude::*;
 use ndarray::prelude::*;
+use std::collections::HashMap;
 fn main() {
     nannou::app(model).update(update).run();
 }
 
 struct Model {
     environment: lidar::Environment,
+    lidar: lidar::Lidar,
     mouse_pos: Vec2,
     texture: wgpu::Texture,
-    scan: Vec<Point2>,
+    scan: Array2<f64>,
+    last_scan: Option<laser_scan::LaserScan>,
     show_ground_truth: bool,
     robot: diff_drive::Robot,
     mouse_is_lidar: bool,
     pose_graph: pose_graph::PoseGraph,
+    mcl: mcl::ParticleFilter,
 }
 
 const M2PIXEL: f32 = 100.0;
@@ -30,61 +33,79 @@ fn model(app: &App) -> Model {
 
     let assets = app.assets_path().unwrap();
     let img_path = assets.join("maps").join("floor.jpg");
-    let texture = wgpu::Texture::from_path(app, img_path.clone()).unwrap();
-    let img = ImageReader::open(img_path)
-        .unwrap()
-        .decode()
-        .unwrap()
-        .to_rgb8();
-
-    let binary_pixels = img
-        .pixels()
-        .map(|pixel| pixel[0] < 40 && pixel[1] < 40 && pixel[2] < 40)
-        .collect();
-    let grid = Array::from_shape_vec((img.height() as usize, img.width() as usize), binary_pixels)
-        .unwrap();
-    println!("{:?}", grid.shape());
-
-    let environment_dims = lidar::Dimension {
-        width: grid.shape()[1],
-        height: grid.shape()[0],
-    };
-
-    let environment = lidar::Environment {
-        grid,
-        dimensions: environment_dims,
-    };
+    let texture = wgpu::Texture::from_path(app, &img_path).unwrap();
+
+    // map image is drawn centred in the window at M2PIXEL pixels per metre
+    let environment = lidar::map::load_image_map(
+        &img_path,
+        lidar::map::OccupancyRule::DarkerThan(40),
+        1. / M2PIXEL,
+        None,
+    )
+    .unwrap_or_else(|e| panic!("Failed to load {}: {}", img_path.display(), e));
+    println!("{:?}", environment.grid.shape());
 
     let pose_graph = pose_graph::PoseGraph {
         nodes: vec![array![[1.,0.,0.], [0.,1.,0.], [0.,0.,1.]]],
         edges: vec![],
+        scans: HashMap::new(),
+    };
+
+    // wheel encoders with slight calibration error and slip, so odometry drifts from the true pose
+    let odometry = diff_drive::odometry::OdometryConfig {
+        encoders: Some(diff_drive::odometry::WheelEncoderConfig {
+            left_radius_error: 0.01,
+            slip_std_dev: 0.02,
+            ..Default::default()
+        }),
+        ..Default::default()
     };
+    let robot =
+        diff_drive::Robot::with_odometry(diff_drive::DiffDriveKinematics::default(), odometry);
+
+    // localise the robot against the map, starting from its known initial pose
+    let mut mcl = mcl::ParticleFilter::new(&environment, mcl::MclConfig::default());
+    mcl.init_gaussian(robot.state.pose, 0.2, 0.1);
 
     Model {
         environment,
+        lidar: lidar::Lidar::new(lidar::LidarConfig::default()),
         mouse_pos: pt2(0.0, 0.0),
         texture,
-        scan: vec![],
+        scan: Array2::zeros((0, 3)),
+        last_scan: None,
         show_ground_truth: false,
-        robot: diff_drive::Robot::new(0.3),
+        robot,
         pose_graph,
+        mcl,
         mouse_is_lidar: true,
     }
 }
 
-fn update(_app: &App, model: &mut Model, _update: Update) {
-    model.robot.step(0.167);
-    let robot_coords = if model.mouse_is_lidar {
-        model.mouse_pos
+fn update(_app: &App, model: &mut Model, update: Update) {
+    // the robot halts against walls and dynamic objects, and waits for a new command
+    if model.robot.step_in(0.167, &model.environment).is_some() {
+        model.robot.set_command(diff_drive::RobotCommand::Stop);
+    }
+    model.environment.step(0.167);
+    let robot_pose = if model.mouse_is_lidar {
+        diff_drive::Pose {
+            x: model.mouse_pos.x / M2PIXEL,
+            y: model.mouse_pos.y / M2PIXEL,
+            theta: 0.,
+        }
     } else {
-        pt2(
-            M2PIXEL * model.robot.state.pose.x,
-            M2PIXEL * model.robot.state.pose.y,
-        )
+        model.robot.state.pose
     };
-    let coords = lidar::point_to_pixel_coords(robot_coords, model.environment.dimensions);
+    let coords = model.environment.world_to_grid(robot_pose.x, robot_pose.y);
     if coords.is_some() {
-        model.scan = lidar::scan_from_point(robot_coords, &model.environment);
+        let timestamp = update.since_start.as_secs_f64();
+        let scan = model.lidar.scan(robot_pose, timestamp, &model.environment);
+        model.scan = scan.to_cloud_at(model.lidar.sensor_pose(robot_pose));
+        if !model.mouse_is_lidar {
+            model.mcl.update(model.robot.odometry.pose, &scan);
+        }
+        model.last_scan = Some(scan);
     }
 }
 
@@ -95,8 +116,15 @@ fn event(app: &App, model: &mut Model, event: WindowEvent) {
         }
         KeyPressed(Key::M) => model.show_ground_truth = !model.show_ground_truth,
         KeyPressed(Key::L) => model.mouse_is_lidar = !model.mouse_is_lidar,
-        // Take measurement with space bar
-        KeyPressed(Key::Space) => model.pose_graph.add_measurement(model.robot.state.pose),
+        // Restart localisation with particles spread over the whole map
+        KeyPressed(Key::G) => model.mcl.init_uniform(),
+        // Take measurement with space bar, keeping the scan so the map can be rebuilt later
+        KeyPressed(Key::Space) => match model.last_scan.clone() {
+            Some(scan) if !model.mouse_is_lidar => model
+                .pose_graph
+                .add_keyframe(model.robot.odometry.pose, scan),
+            _ => model.pose_graph.add_measurement(model.robot.odometry.pose),
+        },
         // Robot movement with arrow keys
         KeyPressed(Key::Right) => model.robot.set_command(diff_drive::RobotCommand::TurnRight),
         KeyPressed(Key::Left) => model.robot.set_command(diff_drive::RobotCommand::TurnLeft),
@@ -141,15 +169,28 @@ fn view(app: &App, model: &Model, frame: Frame) {
 
     // Display the current scan points
     let scan_point_radius = 1.;
-    for pt in &model.scan {
+    for pt in model.scan.outer_iter() {
         draw.ellipse()
-            .x_y(pt.x, pt.y)
+            .x_y(M2PIXEL * pt[0] as f32, M2PIXEL * pt[1] as f32)
             .radius(scan_point_radius)
             .color(nannou::color::RED);
     }
 
-    // Display the current robot state
+    // Display the current robot state, its odometry and where localisation thinks it is
     if !model.mouse_is_lidar {
+        for particle in &model.mcl.particles {
+            draw.ellipse()
+                .x_y(M2PIXEL * particle.pose.x, M2PIXEL * particle.pose.y)
+                .radius(1.)
+                .color(nannou::color::GREEN);
+        }
+        draw::draw_pose(model.mcl.estimate(), &draw, M2PIXEL, nannou::color::GREEN);
+        draw::draw_pose(
+            model.robot.odometry.pose,
+            &draw,
+            M2PIXEL,
+            nannou::color::YELLOW,
+        );
         draw::draw_pose(model.robot.state.pose, &draw, M2PIXEL, nannou::color::ORANGE);
     }
 
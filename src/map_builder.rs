@@ -0,0 +1,280 @@
+// Occupancy grids rendered from the keyframe scans of a pose graph, so the map follows the
+// corrected poses after the graph is optimised
+use crate::diff_drive::Pose;
+use crate::lidar::GridGeometry;
+use crate::occupancy_grid::{OccupancyGrid, OccupancyGridConfig};
+use crate::pose_graph::PoseGraph;
+use std::collections::HashMap;
+
+// render every keyframe scan from scratch, sensor_mount is the sensor pose relative to the nodes
+pub fn rebuild_map(
+    pose_graph: &PoseGraph,
+    geometry: GridGeometry,
+    config: OccupancyGridConfig,
+    sensor_mount: Pose,
+) -> OccupancyGrid {
+    let mut grid = OccupancyGrid::new(geometry, config);
+    let mut node_ids: Vec<&usize> = pose_graph.scans.keys().collect();
+    node_ids.sort();
+    for node_id in node_ids {
+        let sensor_pose = pose_graph.pose(*node_id).compose(sensor_mount);
+        grid.integrate_scan(&pose_graph.scans[node_id], sensor_pose);
+    }
+    grid
+}
+
+// Keeps a map in step with a pose graph, re-rendering only keyframes that were added, removed or
+// moved by more than the thresholds since the last update. Scans are accumulated without
+// clamping so a keyframe can be removed exactly at its old pose; clamping is applied in map().
+pub struct IncrementalMapBuilder {
+    pub config: OccupancyGridConfig,
+    pub sensor_mount: Pose,
+    pub translation_threshold: f32, // metres
+    pub rotation_threshold: f32,    // radians
+    accumulator: OccupancyGrid,
+    rendered: HashMap<usize, Pose>, // sensor pose each keyframe is currently rendered at
+}
+
+impl IncrementalMapBuilder {
+    pub fn new(
+        geometry: GridGeometry,
+        config: OccupancyGridConfig,
+        sensor_mount: Pose,
+        translation_threshold: f32,
+        rotation_threshold: f32,
+    ) -> Self {
+        let unclamped = OccupancyGridConfig {
+            p_min: 0.,
+            p_max: 1.,
+            ..config
+        };
+        IncrementalMapBuilder {
+            config,
+            sensor_mount,
+            translation_threshold,
+            rotation_threshold,
+            accumulator: OccupancyGrid::new(geometry, unclamped),
+            rendered: HashMap::new(),
+        }
+    }
+
+    // bring the map up to date with the graph, returns how many keyframes were re-rendered
+    pub fn update(&mut self, pose_graph: &PoseGraph) -> usize {
+        let mut changed = 0;
+
+        // a scan that is gone from the graph can't be subtracted, so start again from scratch
+        if self
+            .rendered
+            .keys()
+            .any(|node_id| !pose_graph.scans.contains_key(node_id))
+        {
+            self.accumulator.clear();
+            self.rendered.clear();
+        }
+
+        let mut node_ids: Vec<&usize> = pose_graph.scans.keys().collect();
+        node_ids.sort();
+        for node_id in node_ids {
+            let scan = &pose_graph.scans[node_id];
+            let sensor_pose = pose_graph.pose(*node_id).compose(self.sensor_mount);
+            match self.rendered.get(node_id) {
+                Some(old_pose) if !self.moved(*old_pose, sensor_pose) => continue,
+                Some(old_pose) => {
+                    self.accumulator
+                        .integrate_scan_weighted(scan, *old_pose, -1.);
+                }
+                None => (),
+            }
+            self.accumulator.integrate_scan(scan, sensor_pose);
+            self.rendered.insert(*node_id, sensor_pose);
+            changed += 1;
+        }
+        changed
+    }
+
+    // the current map, clamped as configured
+    pub fn map(&self) -> OccupancyGrid {
+        let mut grid = OccupancyGrid::new(self.accumulator.geometry, self.config);
+        let (l_min, l_max) = self.config.log_odds_limits();
+        grid.log_odds = self.accumulator.log_odds.mapv(|l| l.clamp(l_min, l_max));
+        grid
+    }
+
+    fn moved(&self, old: Pose, new: Pose) -> bool {
+        let translation = ((new.x - old.x).powi(2) + (new.y - old.y).powi(2)).sqrt();
+        let dtheta = new.theta - old.theta;
+        let rotation = dtheta.sin().atan2(dtheta.cos()).abs();
+        translation > self.translation_threshold || rotation > self.rotation_threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser_scan::LaserScan;
+    use crate::lidar::Dimension;
+    use approx::assert_abs_diff_eq;
+    use ndarray::prelude::*;
+    use std::f32::consts::PI;
+
+    const MOUNT: Pose = Pose {
+        x: 0.1,
+        y: 0.,
+        theta: 0.,
+    };
+
+    // 3 x 3 m at 5 cm
+    fn geometry() -> GridGeometry {
+        let dimensions = Dimension {
+            width: 60,
+            height: 60,
+        };
+        GridGeometry::centred(dimensions, 0.05)
+    }
+
+    // limits that a few scans never reach
+    fn unclamped_config() -> OccupancyGridConfig {
+        OccupancyGridConfig {
+            p_min: 1e-4,
+            p_max: 1. - 1e-4,
+            ..OccupancyGridConfig::default()
+        }
+    }
+
+    // 12 returns at 0.6 m, with a gap behind the sensor
+    fn scan() -> LaserScan {
+        let mut ranges = vec![0.6; 12];
+        ranges[0] = f32::INFINITY;
+        LaserScan {
+            timestamp: 0.,
+            angle_min: -PI,
+            angle_increment: PI / 6.,
+            time_increment: 0.,
+            scan_time: 0.,
+            range_min: 0.1,
+            range_max: 2.,
+            ranges,
+            intensities: None,
+        }
+    }
+
+    fn pose(x: f32, y: f32, theta: f32) -> Pose {
+        Pose { x, y, theta }
+    }
+
+    fn graph(keyframes: &[Pose]) -> PoseGraph {
+        let mut graph = PoseGraph {
+            nodes: vec![Array2::eye(3)],
+            edges: vec![],
+            scans: HashMap::new(),
+        };
+        for &keyframe in keyframes {
+            graph.add_keyframe(keyframe, scan());
+        }
+        graph
+    }
+
+    // what optimising the graph does to a node
+    fn move_node(graph: &mut PoseGraph, node_id: usize, pose: Pose) {
+        graph.nodes[node_id].assign(&array![
+            [pose.theta.cos(), -pose.theta.sin(), pose.x],
+            [pose.theta.sin(), pose.theta.cos(), pose.y],
+            [0., 0., 1.],
+        ]);
+    }
+
+    fn assert_same_map(a: &OccupancyGrid, b: &OccupancyGrid) {
+        for (x, y) in a.log_odds.iter().zip(b.log_odds.iter()) {
+            assert_abs_diff_eq!(x, y, epsilon = 1e-4);
+        }
+    }
+
+    // the map of the keyframe scans, each integrated directly at its sensor pose
+    fn expected_map(keyframes: &[Pose], config: OccupancyGridConfig) -> OccupancyGrid {
+        let mut grid = OccupancyGrid::new(geometry(), config);
+        for keyframe in keyframes {
+            grid.integrate_scan(&scan(), keyframe.compose(MOUNT));
+        }
+        grid
+    }
+
+    #[test]
+    fn rebuild_map_follows_moved_poses() {
+        let config = OccupancyGridConfig::default();
+        let mut graph = graph(&[pose(0., 0., 0.), pose(0.5, 0.2, 0.3)]);
+        let before = rebuild_map(&graph, geometry(), config, MOUNT);
+        assert_same_map(
+            &before,
+            &expected_map(&[pose(0., 0., 0.), pose(0.5, 0.2, 0.3)], config),
+        );
+
+        move_node(&mut graph, 2, pose(-0.4, -0.3, 1.));
+        let after = rebuild_map(&graph, geometry(), config, MOUNT);
+        assert_same_map(
+            &after,
+            &expected_map(&[pose(0., 0., 0.), pose(-0.4, -0.3, 1.)], config),
+        );
+        assert!(after.occupied() != before.occupied());
+    }
+
+    #[test]
+    fn update_rerenders_only_keyframes_moved_past_the_thresholds() {
+        let mut graph = graph(&[pose(0., 0., 0.), pose(0.5, 0., 0.), pose(0., 0.5, 0.)]);
+        let mut builder =
+            IncrementalMapBuilder::new(geometry(), unclamped_config(), MOUNT, 0.05, 0.1);
+        assert_eq!(builder.update(&graph), 3);
+        assert_eq!(builder.update(&graph), 0);
+
+        // nudged by less than the thresholds, so the map keeps the old pose
+        move_node(&mut graph, 1, pose(0.02, 0.02, 0.05));
+        assert_eq!(builder.update(&graph), 0);
+        assert_same_map(
+            &builder.map(),
+            &expected_map(
+                &[pose(0., 0., 0.), pose(0.5, 0., 0.), pose(0., 0.5, 0.)],
+                unclamped_config(),
+            ),
+        );
+
+        // one moved and one turned past the thresholds
+        move_node(&mut graph, 2, pose(0.6, 0., 0.));
+        move_node(&mut graph, 3, pose(0., 0.5, 0.2));
+        assert_eq!(builder.update(&graph), 2);
+
+        // a new keyframe is rendered on its own
+        graph.add_keyframe(pose(-0.5, -0.5, 0.), scan());
+        assert_eq!(builder.update(&graph), 1);
+    }
+
+    #[test]
+    fn removing_a_keyframe_rebuilds_the_map() {
+        let mut graph = graph(&[pose(0., 0., 0.), pose(0.5, 0., 0.), pose(0., 0.5, 0.)]);
+        let mut builder =
+            IncrementalMapBuilder::new(geometry(), unclamped_config(), MOUNT, 0.05, 0.1);
+        builder.update(&graph);
+
+        graph.scans.remove(&2);
+        assert_eq!(builder.update(&graph), 2);
+        assert_same_map(
+            &builder.map(),
+            &expected_map(&[pose(0., 0., 0.), pose(0., 0.5, 0.)], unclamped_config()),
+        );
+    }
+
+    #[test]
+    fn map_matches_a_full_rebuild_without_clamping() {
+        let config = unclamped_config();
+        let mut graph = graph(&[pose(0., 0., 0.), pose(0.5, 0.1, 0.5), pose(-0.2, 0.6, -1.)]);
+        let mut builder = IncrementalMapBuilder::new(geometry(), config, MOUNT, 0.05, 0.1);
+        builder.update(&graph);
+
+        // moved keyframes are subtracted at their old poses and added at their new ones
+        move_node(&mut graph, 2, pose(0.3, -0.2, 0.8));
+        move_node(&mut graph, 3, pose(-0.3, 0.5, -0.6));
+        builder.update(&graph);
+        let rebuilt = rebuild_map(&graph, geometry(), config, MOUNT);
+        assert_same_map(&builder.map(), &rebuilt);
+        let (l_min, l_max) = config.log_odds_limits();
+        assert!(rebuilt.log_odds.iter().all(|&l| l > l_min && l < l_max));
+    }
+}
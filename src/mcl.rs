@@ -0,0 +1,504 @@
+// Monte Carlo localisation against a known map, following Probabilistic Robotics by S. Thrun et al.
+// (ch. 8.3) with the KLD-sampling of D. Fox, "Adapting the Sample Size in Particle Filters Through
+// KLD-Sampling" to shrink the particle set once the robot is localised
+use crate::diff_drive::{normalise_angle, OdometryMotionModel, Pose};
+use crate::laser_scan::LaserScan;
+use crate::lidar::{Environment, PixelCoord};
+use crate::likelihood_field::{LikelihoodField, LikelihoodFieldConfig};
+use crate::measurement_model::BeamModel;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use std::collections::HashSet;
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone, Debug)]
+pub enum SensorModel {
+    LikelihoodField(LikelihoodFieldConfig),
+    Beam(BeamModel), // ray casts every used beam, slower but models what the beam passed through
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MclConfig {
+    pub min_particles: usize,
+    pub max_particles: usize,
+    pub kld_epsilon: f32, // bound on the KL divergence between the particles and the posterior
+    pub kld_z: f32,       // upper standard normal quantile of the confidence in that bound
+    pub bin_size: f32,    // metres, KLD histogram bins in x and y
+    pub bin_angle: f32,   // radians, KLD histogram bins in heading
+    pub max_beams: usize, // beams used per scan, evenly spaced
+    pub update_min_distance: f32, // odometry must move this far or turn update_min_angle to update
+    pub update_min_angle: f32,
+    pub resample_threshold: f32, // resample when the effective sample size is below this fraction
+    pub motion_model: OdometryMotionModel,
+    pub sensor_model: SensorModel,
+    pub sensor_mount: Pose, // pose of the lidar on the robot
+    pub seed: Option<u64>,  // None seeds from entropy
+}
+
+impl Default for MclConfig {
+    // close to the ROS amcl defaults, with the KLD bound used by Fox
+    fn default() -> Self {
+        MclConfig {
+            min_particles: 100,
+            max_particles: 5000,
+            kld_epsilon: 0.05,
+            kld_z: 2.33,
+            bin_size: 0.5,
+            bin_angle: 10. * PI / 180.,
+            max_beams: 30,
+            update_min_distance: 0.2,
+            update_min_angle: PI / 6.,
+            resample_threshold: 0.5,
+            motion_model: OdometryMotionModel::default(),
+            sensor_model: SensorModel::LikelihoodField(LikelihoodFieldConfig::default()),
+            sensor_mount: Pose::default(),
+            seed: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Particle {
+    pub pose: Pose,
+    pub weight: f64,
+}
+
+pub struct ParticleFilter {
+    pub config: MclConfig,
+    pub particles: Vec<Particle>,
+    map: Environment,
+    field: Option<LikelihoodField>,
+    last_odometry: Option<Pose>, // odometry at the last filter update
+    rng: StdRng,
+}
+
+impl ParticleFilter {
+    // filter over the static grid of the environment, with particles spread over free space
+    pub fn new(map: &Environment, config: MclConfig) -> Self {
+        let field = match config.sensor_model {
+            SensorModel::LikelihoodField(field_config) => {
+                Some(LikelihoodField::new(map, field_config))
+            }
+            SensorModel::Beam(_) => None,
+        };
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut filter = ParticleFilter {
+            config,
+            particles: vec![],
+            map: map.clone(),
+            field,
+            last_odometry: None,
+            rng,
+        };
+        filter.map.dynamic_objects.clear();
+        filter.init_uniform();
+        filter
+    }
+
+    // global localisation: max_particles spread uniformly over the free cells of the map
+    pub fn init_uniform(&mut self) {
+        let free: Vec<PixelCoord> = self
+            .map
+            .grid
+            .indexed_iter()
+            .filter(|(_, occupied)| !**occupied)
+            .map(|((y, x), _)| PixelCoord { x, y })
+            .collect();
+        if free.is_empty() {
+            self.particles.clear();
+            return;
+        }
+        let resolution = self.map.geometry.resolution;
+        let count = self.config.max_particles;
+        let weight = 1. / count as f64;
+        self.particles = (0..count)
+            .map(|_| {
+                let (x, y) = self
+                    .map
+                    .grid_to_world(free[self.rng.gen_range(0..free.len())]);
+                let pose = Pose {
+                    x: x + self.rng.gen_range(-0.5..0.5) * resolution,
+                    y: y + self.rng.gen_range(-0.5..0.5) * resolution,
+                    theta: self.rng.gen_range(-PI..PI),
+                };
+                Particle { pose, weight }
+            })
+            .collect();
+    }
+
+    // tracking from a rough initial guess
+    pub fn init_gaussian(&mut self, mean: Pose, position_std_dev: f32, heading_std_dev: f32) {
+        let position = Normal::new(0., position_std_dev.max(f32::EPSILON)).unwrap();
+        let heading = Normal::new(0., heading_std_dev.max(f32::EPSILON)).unwrap();
+        let count = self.config.max_particles;
+        let weight = 1. / count as f64;
+        self.particles = (0..count)
+            .map(|_| {
+                let pose = Pose {
+                    x: mean.x + position.sample(&mut self.rng),
+                    y: mean.y + position.sample(&mut self.rng),
+                    theta: normalise_angle(mean.theta + heading.sample(&mut self.rng)),
+                };
+                Particle { pose, weight }
+            })
+            .collect();
+    }
+
+    // Feed the latest odometry pose and scan. The filter only updates once the odometry has moved
+    // far enough since the last update, returns whether it did.
+    pub fn update(&mut self, odometry: Pose, scan: &LaserScan) -> bool {
+        let last = match self.last_odometry {
+            Some(last) => last,
+            None => {
+                self.last_odometry = Some(odometry);
+                return false;
+            }
+        };
+        let distance = ((odometry.x - last.x).powi(2) + (odometry.y - last.y).powi(2)).sqrt();
+        let angle = normalise_angle(odometry.theta - last.theta).abs();
+        if distance < self.config.update_min_distance && angle < self.config.update_min_angle {
+            return false;
+        }
+        self.last_odometry = Some(odometry);
+
+        self.predict(last, odometry);
+        self.weigh(scan);
+        if self.effective_sample_size()
+            < self.config.resample_threshold as f64 * self.particles.len() as f64
+        {
+            self.resample();
+        }
+        true
+    }
+
+    // motion update, moving every particle by a sample of the odometry motion model
+    pub fn predict(&mut self, odom_prev: Pose, odom_now: Pose) {
+        let motion_model = self.config.motion_model;
+        for particle in &mut self.particles {
+            particle.pose = motion_model.sample(particle.pose, odom_prev, odom_now, &mut self.rng);
+        }
+    }
+
+    // measurement update, multiplying the weights by the scan likelihood and normalising
+    pub fn weigh(&mut self, scan: &LaserScan) {
+        let step = (scan.len() / self.config.max_beams.max(1)).max(1);
+        let log_likelihoods: Vec<f64> = self
+            .particles
+            .iter()
+            .map(|particle| {
+                let sensor_pose = particle.pose.compose(self.config.sensor_mount);
+                let log_likelihood = match (&self.field, self.config.sensor_model) {
+                    (Some(field), _) => field.log_likelihood(scan, sensor_pose, step),
+                    (None, SensorModel::Beam(model)) => {
+                        beam_log_likelihood(&self.map, &model, scan, sensor_pose, step)
+                    }
+                    (None, SensorModel::LikelihoodField(_)) => 0.,
+                };
+                particle.weight.ln() + log_likelihood
+            })
+            .collect();
+
+        // subtract the largest before exponentiating so the weights don't all underflow to zero
+        let max = log_likelihoods
+            .iter()
+            .cloned()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if !max.is_finite() {
+            let weight = 1. / self.particles.len() as f64;
+            self.particles.iter_mut().for_each(|p| p.weight = weight);
+            return;
+        }
+        for (particle, l) in self.particles.iter_mut().zip(log_likelihoods) {
+            particle.weight = (l - max).exp();
+        }
+        self.normalise();
+    }
+
+    pub fn effective_sample_size(&self) -> f64 {
+        1. / self
+            .particles
+            .iter()
+            .map(|p| p.weight * p.weight)
+            .sum::<f64>()
+    }
+
+    // Low-variance resampling to the number of particles KLD-sampling asks for, given how many
+    // histogram bins the current weighted particles cover
+    pub fn resample(&mut self) {
+        if self.particles.is_empty() {
+            return;
+        }
+        let bins: HashSet<(i32, i32, i32)> = self
+            .particles
+            .iter()
+            .filter(|p| p.weight > 0.)
+            .map(|p| self.bin(p.pose))
+            .collect();
+        let count = self.kld_particle_count(bins.len());
+
+        let weights: Vec<f64> = self.particles.iter().map(|p| p.weight).collect();
+        let weight = 1. / count as f64;
+        let resampled = low_variance_resample(&weights, count, &mut self.rng)
+            .into_iter()
+            .map(|i| Particle {
+                pose: self.particles[i].pose,
+                weight,
+            })
+            .collect();
+        self.particles = resampled;
+    }
+
+    // Particles needed so that, with the configured confidence, the KL divergence between the
+    // sample-based and true posterior over k occupied bins stays below kld_epsilon
+    pub fn kld_particle_count(&self, k: usize) -> usize {
+        let MclConfig {
+            min_particles,
+            max_particles,
+            kld_epsilon,
+            kld_z,
+            ..
+        } = self.config;
+        if k <= 1 {
+            return min_particles.min(max_particles);
+        }
+        let k = (k - 1) as f32;
+        let a = 2. / (9. * k);
+        let n = k / (2. * kld_epsilon) * (1. - a + a.sqrt() * kld_z).powi(3);
+        (n.ceil() as usize).clamp(min_particles, max_particles)
+    }
+
+    // weighted mean of the particles, taking the circular mean of the headings
+    pub fn estimate(&self) -> Pose {
+        let (mut x, mut y, mut sin, mut cos, mut total) = (0., 0., 0., 0., 0.);
+        for particle in &self.particles {
+            let w = particle.weight;
+            x += w * particle.pose.x as f64;
+            y += w * particle.pose.y as f64;
+            sin += w * (particle.pose.theta as f64).sin();
+            cos += w * (particle.pose.theta as f64).cos();
+            total += w;
+        }
+        if total <= 0. {
+            return Pose::default();
+        }
+        Pose {
+            x: (x / total) as f32,
+            y: (y / total) as f32,
+            theta: sin.atan2(cos) as f32,
+        }
+    }
+
+    fn normalise(&mut self) {
+        let total: f64 = self.particles.iter().map(|p| p.weight).sum();
+        if total > 0. {
+            self.particles.iter_mut().for_each(|p| p.weight /= total);
+        }
+    }
+
+    fn bin(&self, pose: Pose) -> (i32, i32, i32) {
+        (
+            (pose.x / self.config.bin_size).floor() as i32,
+            (pose.y / self.config.bin_size).floor() as i32,
+            (normalise_angle(pose.theta) / self.config.bin_angle).floor() as i32,
+        )
+    }
+}
+
+// Indices of count particles drawn in proportion to their normalised weights with a single random
+// number, so particles of equal weight survive evenly (Probabilistic Robotics table 4.4)
+pub fn low_variance_resample<R: Rng>(weights: &[f64], count: usize, rng: &mut R) -> Vec<usize> {
+    if weights.is_empty() || count == 0 {
+        return vec![];
+    }
+    let step = 1. / count as f64;
+    let mut target = rng.gen_range(0.0..step);
+    let mut cumulative = weights[0];
+    let mut i = 0;
+    let mut indices = Vec::with_capacity(count);
+    for _ in 0..count {
+        while target > cumulative && i + 1 < weights.len() {
+            i += 1;
+            cumulative += weights[i];
+        }
+        indices.push(i);
+        target += step;
+    }
+    indices
+}
+
+// log likelihood of every step-th beam under the beam model, ray casting the expected ranges
+fn beam_log_likelihood(
+    map: &Environment,
+    model: &BeamModel,
+    scan: &LaserScan,
+    sensor_pose: Pose,
+    step: usize,
+) -> f64 {
+    let mut total = 0.;
+    for (i, range) in scan.ranges.iter().enumerate().step_by(step) {
+        if range.is_nan() {
+            continue;
+        }
+        let ray = Pose {
+            theta: sensor_pose.theta + scan.angle(i),
+            ..sensor_pose
+        };
+        let expected = map
+            .cast_ray_static(ray, scan.range_max)
+            .unwrap_or(scan.range_max);
+        let measured = range.min(scan.range_max);
+        total += (model.likelihood(measured, expected, scan.range_max) as f64).ln();
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::{Lidar, LidarConfig};
+    use approx::assert_abs_diff_eq;
+    use ndarray::Array2;
+
+    // 4 x 3 m room with 10 cm walls and a 0.5 m box towards one corner, so no pose looks alike
+    fn room() -> Environment {
+        let mut grid = Array2::from_elem((60, 80), false);
+        for ((row, col), cell) in grid.indexed_iter_mut() {
+            let wall = !(2..58).contains(&row) || !(2..78).contains(&col);
+            let obstacle = (10..20).contains(&row) && (55..65).contains(&col);
+            *cell = wall || obstacle;
+        }
+        Environment::centred(grid, 0.05)
+    }
+
+    fn filter(min_particles: usize, max_particles: usize) -> ParticleFilter {
+        let config = MclConfig {
+            min_particles,
+            max_particles,
+            seed: Some(3),
+            ..MclConfig::default()
+        };
+        ParticleFilter::new(&room(), config)
+    }
+
+    fn lidar() -> Lidar {
+        Lidar::new(LidarConfig {
+            beam_count: 180,
+            max_range: 5.,
+            range_noise_std_dev: 0.01,
+            seed: Some(7),
+            ..LidarConfig::default()
+        })
+    }
+
+    fn pose(x: f32, y: f32, theta: f32) -> Pose {
+        Pose { x, y, theta }
+    }
+
+    #[test]
+    fn low_variance_resample_keeps_equal_weights_evenly() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let weights = [0.25; 4];
+        assert_eq!(low_variance_resample(&weights, 4, &mut rng), [0, 1, 2, 3]);
+        assert_eq!(
+            low_variance_resample(&weights, 8, &mut rng),
+            [0, 0, 1, 1, 2, 2, 3, 3]
+        );
+        assert!(low_variance_resample(&weights, 0, &mut rng).is_empty());
+        assert!(low_variance_resample(&[], 4, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn low_variance_resample_follows_the_weights() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let weights = [0.1, 0., 0.6, 0.3];
+        for _ in 0..20 {
+            let indices = low_variance_resample(&weights, 1000, &mut rng);
+            // a single random offset means each count is within one of its expected value
+            for (i, weight) in weights.iter().enumerate() {
+                let count = indices.iter().filter(|&&index| index == i).count() as f64;
+                assert!(
+                    (count - 1000. * weight).abs() <= 1.,
+                    "{} copies of {}",
+                    count,
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn kld_particle_count_grows_with_the_bins_within_the_limits() {
+        let filter = filter(50, 2000);
+        assert_eq!(filter.kld_particle_count(0), 50);
+        assert_eq!(filter.kld_particle_count(1), 50);
+        assert_eq!(filter.kld_particle_count(10_000), 2000);
+        let counts: Vec<usize> = (1..500).map(|k| filter.kld_particle_count(k)).collect();
+        assert!(counts.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(counts.iter().any(|&n| n > 50 && n < 2000));
+    }
+
+    #[test]
+    fn weigh_falls_back_to_uniform_weights() {
+        let mut filter = filter(10, 100);
+        let scan = lidar().scan(Pose::default(), 0., &room());
+        // every log likelihood is -inf once the weights are all zero
+        filter.particles.iter_mut().for_each(|p| p.weight = 0.);
+        filter.weigh(&scan);
+        for particle in &filter.particles {
+            assert_abs_diff_eq!(particle.weight, 0.01);
+        }
+    }
+
+    #[test]
+    fn update_waits_for_enough_motion() {
+        let mut filter = filter(10, 100);
+        let scan = lidar().scan(Pose::default(), 0., &room());
+        let xs: Vec<f32> = filter.particles.iter().map(|p| p.pose.x).collect();
+        let unchanged = |filter: &ParticleFilter| {
+            filter
+                .particles
+                .iter()
+                .zip(&xs)
+                .all(|(p, x)| p.pose.x == *x)
+        };
+
+        // the first odometry reading only sets the reference
+        assert!(!filter.update(Pose::default(), &scan));
+        assert!(!filter.update(pose(0.15, 0.1, 0.5), &scan));
+        assert!(unchanged(&filter));
+
+        assert!(filter.update(pose(0.2, 0., 0.), &scan));
+        assert!(!unchanged(&filter));
+        assert!(!filter.update(pose(0.3, 0., 0.5), &scan));
+        assert!(filter.update(pose(0.3, 0., 0.6), &scan));
+    }
+
+    #[test]
+    fn localisation_converges_from_a_rough_guess() {
+        let room = room();
+        let mut lidar = lidar();
+        let mut filter = filter(100, 1000);
+        let start = pose(-1.4, -0.9, 0.2);
+        filter.init_gaussian(pose(-1.2, -1.1, 0.4), 0.2, 0.2);
+
+        // driving an arc with perfect odometry, far enough each step to update the filter
+        let mut true_pose = start;
+        filter.update(true_pose, &lidar.scan(true_pose, 0., &room));
+        for step in 1..=10 {
+            true_pose = true_pose.compose(pose(0.25, 0., 0.1));
+            let scan = lidar.scan(true_pose, step as f64, &room);
+            assert!(filter.update(true_pose, &scan));
+        }
+        let estimate = filter.estimate();
+        let error =
+            ((estimate.x - true_pose.x).powi(2) + (estimate.y - true_pose.y).powi(2)).sqrt();
+        assert!(error < 0.05, "{:?} for {:?}", estimate, true_pose);
+        assert!(normalise_angle(estimate.theta - true_pose.theta).abs() < 0.03);
+        // and the particle set has shrunk once localised
+        assert!(filter.particles.len() < 1000);
+    }
+}
@@ -0,0 +1,255 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use std::f32::consts::PI;
+
+// Turns the true distance along a beam into what the sensor reports. A true range equal to
+// max_range means the beam hit nothing, and a returned range of max_range means no return.
+pub trait MeasurementModel {
+    // angle the beam is actually cast at, the scan still reports the nominal bearing
+    fn perturb_bearing(&self, bearing: f32, _rng: &mut StdRng) -> f32 {
+        bearing
+    }
+    fn measure_range(&self, true_range: f32, max_range: f32, rng: &mut StdRng) -> f32;
+}
+
+// Returns the true range unchanged
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoiseFree;
+
+impl MeasurementModel for NoiseFree {
+    fn measure_range(&self, true_range: f32, _max_range: f32, _rng: &mut StdRng) -> f32 {
+        true_range
+    }
+}
+
+// Mixture of the four failure modes of the beam model in Probabilistic Robotics by S. Thrun et al.
+// (ch. 6.3), plus angular jitter. Each reading is a dropout, a uniformly random reading, a short
+// reading from an unmodelled obstacle, or otherwise a correct hit with Gaussian noise.
+#[derive(Copy, Clone, Debug)]
+pub struct BeamModel {
+    pub range_std_dev: f32,
+    pub range_std_dev_per_unit: f32, // extra std dev per unit of true range
+    pub dropout_probability: f32,    // reports max range
+    pub random_probability: f32,     // reports uniformly over [0, max range)
+    pub short_probability: f32,      // reports exponentially distributed short of the true range
+    pub short_rate: f32,             // rate of the exponential, larger means shorter readings
+    pub angular_std_dev: f32,
+}
+
+impl Default for BeamModel {
+    // noise-free
+    fn default() -> Self {
+        BeamModel {
+            range_std_dev: 0.,
+            range_std_dev_per_unit: 0.,
+            dropout_probability: 0.,
+            random_probability: 0.,
+            short_probability: 0.,
+            short_rate: 1.,
+            angular_std_dev: 0.,
+        }
+    }
+}
+
+impl BeamModel {
+    // constant Gaussian range noise only
+    pub fn gaussian(range_std_dev: f32) -> Self {
+        BeamModel {
+            range_std_dev,
+            ..Default::default()
+        }
+    }
+
+    fn hit_std_dev(&self, true_range: f32) -> f32 {
+        self.range_std_dev + self.range_std_dev_per_unit * true_range
+    }
+
+    // Density of reading measured_range when the true range is true_range, the mixture that
+    // measure_range samples from. A zero hit std dev is treated as 1 mm so the density stays
+    // finite.
+    pub fn likelihood(&self, measured_range: f32, true_range: f32, max_range: f32) -> f32 {
+        let hit_probability =
+            (1. - self.dropout_probability - self.random_probability - self.short_probability)
+                .max(0.);
+        let mut density = 0.;
+
+        if measured_range >= max_range {
+            density += self.dropout_probability;
+            if true_range >= max_range {
+                density += hit_probability;
+            }
+        } else {
+            density += self.random_probability / max_range;
+            if true_range < max_range {
+                let std_dev = self.hit_std_dev(true_range).max(1e-3);
+                let z = (measured_range - true_range) / std_dev;
+                density += hit_probability * (-0.5 * z * z).exp() / (std_dev * (2. * PI).sqrt());
+            }
+            if measured_range < true_range && true_range > 0. {
+                let truncation = 1. - (-self.short_rate * true_range).exp();
+                density += self.short_probability
+                    * self.short_rate
+                    * (-self.short_rate * measured_range).exp()
+                    / truncation;
+            }
+        }
+        density
+    }
+}
+
+impl MeasurementModel for BeamModel {
+    fn perturb_bearing(&self, bearing: f32, rng: &mut StdRng) -> f32 {
+        if self.angular_std_dev > 0. {
+            bearing + Normal::new(0., self.angular_std_dev).unwrap().sample(rng)
+        } else {
+            bearing
+        }
+    }
+
+    fn measure_range(&self, true_range: f32, max_range: f32, rng: &mut StdRng) -> f32 {
+        let u: f32 = rng.gen();
+        let mut threshold = self.dropout_probability;
+        if u < threshold {
+            return max_range;
+        }
+        threshold += self.random_probability;
+        if u < threshold {
+            return rng.gen_range(0.0..max_range);
+        }
+        threshold += self.short_probability;
+        if u < threshold && true_range > 0. {
+            // inverse CDF of an exponential truncated to [0, true_range)
+            let v: f32 = rng.gen();
+            let truncation = 1. - (-self.short_rate * true_range).exp();
+            return -(1. - v * truncation).ln() / self.short_rate;
+        }
+
+        if true_range >= max_range {
+            return max_range;
+        }
+        let std_dev = self.hit_std_dev(true_range);
+        let noise = if std_dev > 0. {
+            Normal::new(0., std_dev).unwrap().sample(rng)
+        } else {
+            0.
+        };
+        (true_range + noise).clamp(0., max_range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff_drive::Pose;
+    use crate::lidar::{Environment, Lidar, LidarConfig};
+    use ndarray::Array2;
+    use rand::SeedableRng;
+
+    const MAX_RANGE: f32 = 5.;
+    const SAMPLES: usize = 1000;
+
+    fn noisy_model() -> BeamModel {
+        BeamModel {
+            range_std_dev: 0.02,
+            range_std_dev_per_unit: 0.01,
+            dropout_probability: 0.05,
+            random_probability: 0.05,
+            short_probability: 0.1,
+            short_rate: 1.,
+            angular_std_dev: 0.01,
+        }
+    }
+
+    fn samples(model: BeamModel, true_range: f32) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..SAMPLES)
+            .map(|_| model.measure_range(true_range, MAX_RANGE, &mut rng))
+            .collect()
+    }
+
+    fn scan_ranges(seed: u64) -> Vec<u32> {
+        // 4 x 4 m room
+        let mut grid = Array2::from_elem((80, 80), false);
+        for ((row, col), cell) in grid.indexed_iter_mut() {
+            *cell = !(1..79).contains(&row) || !(1..79).contains(&col);
+        }
+        let environment = Environment::centred(grid, 0.05);
+        let config = LidarConfig {
+            seed: Some(seed),
+            ..LidarConfig::default()
+        };
+        let mut lidar = Lidar::with_model(config, Box::new(noisy_model()));
+        let scan = lidar.scan(Pose::default(), 0., &environment);
+        // compared bit for bit, as no return is infinite
+        scan.ranges.iter().map(|range| range.to_bits()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_scan() {
+        assert_eq!(scan_ranges(3), scan_ranges(3));
+        assert_ne!(scan_ranges(3), scan_ranges(4));
+    }
+
+    #[test]
+    fn certain_dropout_always_reports_max_range() {
+        let model = BeamModel {
+            dropout_probability: 1.,
+            ..noisy_model()
+        };
+        assert!(samples(model, 2.).iter().all(|range| *range == MAX_RANGE));
+    }
+
+    #[test]
+    fn certain_random_reading_is_uniform_over_the_range() {
+        let model = BeamModel {
+            dropout_probability: 0.,
+            random_probability: 1.,
+            ..noisy_model()
+        };
+        let ranges = samples(model, 2.);
+        assert!(ranges.iter().all(|range| (0.0..MAX_RANGE).contains(range)));
+        let mean = ranges.iter().sum::<f32>() / SAMPLES as f32;
+        assert!((mean - MAX_RANGE / 2.).abs() < 0.2, "mean {}", mean);
+        let below_1m = ranges.iter().filter(|range| **range < 1.).count();
+        assert!((150..250).contains(&below_1m), "{} below 1 m", below_1m);
+    }
+
+    #[test]
+    fn certain_short_reading_falls_short_of_the_true_range() {
+        let model = BeamModel {
+            dropout_probability: 0.,
+            random_probability: 0.,
+            short_probability: 1.,
+            ..noisy_model()
+        };
+        let ranges = samples(model, 2.);
+        assert!(ranges.iter().all(|range| (0.0..2.).contains(range)));
+        // the exponential makes short readings more likely nearer the sensor
+        let nearer = ranges.iter().filter(|range| **range < 1.).count();
+        assert!(
+            nearer > SAMPLES / 2,
+            "{} of {} under halfway",
+            nearer,
+            SAMPLES
+        );
+    }
+
+    #[test]
+    fn likelihood_peaks_at_the_true_range() {
+        let model = noisy_model();
+        let true_range = 2.;
+        let step = 0.001;
+        let (peak, _) = (0..(MAX_RANGE / step) as usize)
+            .map(|i| i as f32 * step)
+            .map(|range| (range, model.likelihood(range, true_range, MAX_RANGE)))
+            .fold((0., 0.), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+        assert!((peak - true_range).abs() <= step, "peak at {}", peak);
+    }
+}
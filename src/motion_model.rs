@@ -0,0 +1,137 @@
+// Platforms the simulator can drive, generalising diff_drive::Robot. Each takes its own kind of
+// command, moves its true pose with its own noise model and dead reckons an odometry pose from
+// what its encoders measure.
+use crate::diff_drive::{integrate, normalise_angle, Pose, Robot, RobotCommand};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, Normal};
+
+pub mod ackermann;
+pub mod mecanum;
+pub mod skid_steer;
+
+pub trait MotionModel {
+    type Command;
+
+    fn set_command(&mut self, command: Self::Command);
+
+    // advance the true and odometry poses by time_step seconds under the current command
+    fn step(&mut self, time_step: f32);
+
+    fn pose(&self) -> Pose; // ground truth
+
+    fn odometry(&self) -> Pose; // what the robot believes from its encoders
+
+    // true forward (m/s), leftward (m/s) and anticlockwise (rad/s) velocity in the robot frame
+    fn velocity(&self) -> (f32, f32, f32);
+}
+
+impl MotionModel for Robot {
+    type Command = RobotCommand;
+
+    fn set_command(&mut self, command: RobotCommand) {
+        Robot::set_command(self, command);
+    }
+
+    fn step(&mut self, time_step: f32) {
+        Robot::step(self, time_step);
+    }
+
+    fn pose(&self) -> Pose {
+        self.state.pose
+    }
+
+    fn odometry(&self) -> Pose {
+        self.odometry.pose
+    }
+
+    fn velocity(&self) -> (f32, f32, f32) {
+        let (linear, angular) = Robot::velocity(self);
+        (linear, 0., angular)
+    }
+}
+
+// Pose after moving at a constant velocity in the robot frame for time_step. The robot frame
+// turns at a steady rate, so the path is the arc diff_drive::integrate follows for a robot
+// facing the direction of travel.
+pub fn integrate_holonomic(
+    pose: Pose,
+    forward_vel: f32,
+    lateral_vel: f32,
+    angular_vel: f32,
+    time_step: f32,
+) -> Pose {
+    let direction = lateral_vel.atan2(forward_vel);
+    let speed = (forward_vel * forward_vel + lateral_vel * lateral_vel).sqrt();
+    let travel = Pose {
+        theta: pose.theta + direction,
+        ..pose
+    };
+    let end = integrate(travel, speed, angular_vel, time_step);
+    Pose {
+        theta: normalise_angle(end.theta - direction),
+        ..end
+    }
+}
+
+fn rng_from_seed(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+// zero mean normal sample with the given std dev
+fn sample_noise(std_dev: f32, rng: &mut StdRng) -> f32 {
+    if std_dev > 0. {
+        Normal::new(0., std_dev).unwrap().sample(rng)
+    } else {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn holonomic_motion_follows_the_analytic_arc() {
+        let start = Pose {
+            x: 0.5,
+            y: -1.,
+            theta: 2.5,
+        };
+        let (forward, lateral, angular) = (0.3, -0.2, 0.7);
+        for &time in [0.1, 1., 4.].iter() {
+            let end = integrate_holonomic(start, forward, lateral, angular, time);
+            // integral of the robot frame velocity rotated by the heading start.theta + angular * t
+            let (sin_0, cos_0) = start.theta.sin_cos();
+            let (sin_t, cos_t) = (start.theta + angular * time).sin_cos();
+            let x = start.x + (forward * (sin_t - sin_0) + lateral * (cos_t - cos_0)) / angular;
+            let y = start.y + (lateral * (sin_t - sin_0) - forward * (cos_t - cos_0)) / angular;
+            assert_abs_diff_eq!(end.x, x, epsilon = 1e-4);
+            assert_abs_diff_eq!(end.y, y, epsilon = 1e-4);
+            assert_abs_diff_eq!(
+                end.theta,
+                normalise_angle(start.theta + angular * time),
+                epsilon = 1e-4
+            );
+        }
+    }
+
+    #[test]
+    fn holonomic_motion_without_turning_is_straight() {
+        let start = Pose {
+            x: 1.,
+            y: 2.,
+            theta: PI / 2.,
+        };
+        // sideways to the left of a robot facing +y is towards -x
+        let end = integrate_holonomic(start, 0., 0.4, 0., 2.);
+        assert_abs_diff_eq!(end.x, 0.2, epsilon = 1e-5);
+        assert_abs_diff_eq!(end.y, 2., epsilon = 1e-5);
+        assert_abs_diff_eq!(end.theta, PI / 2., epsilon = 1e-6);
+    }
+}
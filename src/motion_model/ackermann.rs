@@ -0,0 +1,178 @@
+// Car-like base with steered front wheels, modelled as a bicycle with its pose at the centre of
+// the rear axle. The steering turns towards its commanded angle at a limited rate.
+use super::{rng_from_seed, sample_noise, MotionModel};
+use crate::diff_drive::{integrate, Pose};
+use rand::rngs::StdRng;
+
+// as in a ROS ackermann_msgs/AckermannDrive
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AckermannCommand {
+    pub speed: f32,          // m/s of the rear axle
+    pub steering_angle: f32, // radians of the virtual centre front wheel, positive to the left
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AckermannConfig {
+    pub wheelbase: f32, // metres between the front and rear axles
+    pub max_steering_angle: f32,
+    pub max_steering_rate: f32, // rad/s
+    pub slip_std_dev: f32,      // fraction of the rear wheels' travel they spin or skid
+    pub steering_std_dev: f32,  // radians the wheels wander from the steering angle
+    pub speed_scale_error: f32, // fraction the rear wheel encoder over-reads its travel
+    pub steering_bias: f32,     // radians the steering angle sensor reads to the left of the truth
+    pub seed: Option<u64>,      // None seeds from entropy
+}
+
+impl Default for AckermannConfig {
+    fn default() -> Self {
+        AckermannConfig {
+            wheelbase: 0.3,
+            max_steering_angle: 0.5,
+            max_steering_rate: 1.5,
+            slip_std_dev: 0.02,
+            steering_std_dev: 0.01,
+            speed_scale_error: 0.,
+            steering_bias: 0.,
+            seed: None,
+        }
+    }
+}
+
+pub struct AckermannRobot {
+    pub config: AckermannConfig,
+    pub pose: Pose,
+    pub odometry: Pose,
+    pub steering_angle: f32, // true angle the steering has reached
+    command: AckermannCommand,
+    velocity: (f32, f32), // true linear and angular velocity over the last step
+    rng: StdRng,
+}
+
+impl AckermannRobot {
+    pub fn new(config: AckermannConfig, initial_pose: Pose) -> Self {
+        AckermannRobot {
+            config,
+            pose: initial_pose,
+            odometry: initial_pose,
+            steering_angle: 0.,
+            command: AckermannCommand::default(),
+            velocity: (0., 0.),
+            rng: rng_from_seed(config.seed),
+        }
+    }
+
+    // radius of the circle the rear axle follows at a steering angle, infinite when straight
+    pub fn turning_radius(&self, steering_angle: f32) -> f32 {
+        self.config.wheelbase / steering_angle.tan()
+    }
+}
+
+impl MotionModel for AckermannRobot {
+    type Command = AckermannCommand;
+
+    fn set_command(&mut self, command: AckermannCommand) {
+        self.command = command;
+    }
+
+    // Odometry combines the rear wheel encoder with the steering angle sensor, both of which may
+    // be miscalibrated, while the true motion slips and the wheels wander about the steering angle
+    fn step(&mut self, time_step: f32) {
+        let config = self.config;
+        let target = self
+            .command
+            .steering_angle
+            .clamp(-config.max_steering_angle, config.max_steering_angle);
+        let max_change = config.max_steering_rate * time_step;
+        self.steering_angle += (target - self.steering_angle).clamp(-max_change, max_change);
+
+        let speed = self.command.speed;
+        let ground_speed = speed * (1. + sample_noise(config.slip_std_dev, &mut self.rng));
+        let wheel_angle =
+            self.steering_angle + sample_noise(config.steering_std_dev, &mut self.rng);
+        let angular_vel = ground_speed * wheel_angle.tan() / config.wheelbase;
+        self.velocity = (ground_speed, angular_vel);
+        self.pose = integrate(self.pose, ground_speed, angular_vel, time_step);
+
+        let measured_speed = speed * (1. + config.speed_scale_error);
+        let measured_angle = self.steering_angle + config.steering_bias;
+        let measured_angular_vel = measured_speed * measured_angle.tan() / config.wheelbase;
+        self.odometry = integrate(
+            self.odometry,
+            measured_speed,
+            measured_angular_vel,
+            time_step,
+        );
+    }
+
+    fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    fn odometry(&self) -> Pose {
+        self.odometry
+    }
+
+    fn velocity(&self) -> (f32, f32, f32) {
+        (self.velocity.0, 0., self.velocity.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn noise_free() -> AckermannConfig {
+        AckermannConfig {
+            slip_std_dev: 0.,
+            steering_std_dev: 0.,
+            seed: Some(0),
+            ..AckermannConfig::default()
+        }
+    }
+
+    #[test]
+    fn turning_radius_is_wheelbase_over_tan_steering() {
+        let robot = AckermannRobot::new(noise_free(), Pose::default());
+        assert_abs_diff_eq!(
+            robot.turning_radius(0.3),
+            0.3 / 0.3f32.tan(),
+            epsilon = 1e-6
+        );
+        assert_abs_diff_eq!(
+            robot.turning_radius(-0.3),
+            -0.3 / 0.3f32.tan(),
+            epsilon = 1e-6
+        );
+        assert!(robot.turning_radius(0.).is_infinite());
+    }
+
+    #[test]
+    fn rear_axle_follows_the_turning_circle() {
+        let steering_angle = 0.4;
+        let mut robot = AckermannRobot::new(noise_free(), Pose::default());
+        robot.set_command(AckermannCommand {
+            speed: 0.5,
+            steering_angle,
+        });
+        // let the steering reach its angle, at 1.5 rad/s
+        for _ in 0..3 {
+            robot.step(0.1);
+        }
+        assert_abs_diff_eq!(robot.steering_angle, steering_angle);
+
+        let radius = robot.turning_radius(steering_angle);
+        let (linear, _, angular) = robot.velocity();
+        assert_abs_diff_eq!(linear / angular, radius, epsilon = 1e-5);
+        let pose = robot.pose;
+        let centre_x = pose.x - radius * pose.theta.sin();
+        let centre_y = pose.y + radius * pose.theta.cos();
+        for _ in 0..40 {
+            robot.step(0.1);
+            let (dx, dy) = (robot.pose.x - centre_x, robot.pose.y - centre_y);
+            assert_abs_diff_eq!(dx.hypot(dy), radius, epsilon = 1e-4);
+        }
+        assert_abs_diff_eq!(robot.odometry.x, robot.pose.x, epsilon = 1e-5);
+        assert_abs_diff_eq!(robot.odometry.y, robot.pose.y, epsilon = 1e-5);
+    }
+}
@@ -0,0 +1,218 @@
+// Holonomic base on four mecanum wheels, whose 45 degree rollers let it drive sideways. Wheels are
+// ordered front left, front right, rear left, rear right.
+use super::{integrate_holonomic, rng_from_seed, sample_noise, MotionModel};
+use crate::diff_drive::Pose;
+use rand::rngs::StdRng;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HolonomicTwist {
+    pub forward: f32, // m/s
+    pub lateral: f32, // m/s to the left
+    pub angular: f32, // rad/s anticlockwise
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum MecanumCommand {
+    Twist(HolonomicTwist),
+    WheelVelocities([f32; 4]), // rad/s
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MecanumConfig {
+    pub wheel_radius: f32,
+    pub half_length: f32,  // metres from the centre to the front and rear axles
+    pub half_width: f32,   // metres from the centre to the left and right wheels
+    pub slip_std_dev: f32, // fraction of each wheel's travel it spins or skids
+    pub roller_slip: f32,  // fraction of sideways travel lost to the rollers slipping
+    pub seed: Option<u64>, // None seeds from entropy
+}
+
+impl Default for MecanumConfig {
+    fn default() -> Self {
+        MecanumConfig {
+            wheel_radius: 0.05,
+            half_length: 0.2,
+            half_width: 0.2,
+            slip_std_dev: 0.02,
+            roller_slip: 0.05,
+            seed: None,
+        }
+    }
+}
+
+impl MecanumConfig {
+    // forward, lateral and angular velocity of the base from its wheel speeds in rad/s
+    pub fn body_velocity(&self, wheels: [f32; 4]) -> HolonomicTwist {
+        let [front_left, front_right, rear_left, rear_right] = wheels;
+        let r = self.wheel_radius / 4.;
+        HolonomicTwist {
+            forward: r * (front_left + front_right + rear_left + rear_right),
+            lateral: r * (-front_left + front_right + rear_left - rear_right),
+            angular: r * (-front_left + front_right - rear_left + rear_right)
+                / (self.half_length + self.half_width),
+        }
+    }
+
+    // wheel speeds in rad/s that give the base a twist, inverse of body_velocity
+    pub fn wheel_velocities(&self, twist: HolonomicTwist) -> [f32; 4] {
+        let turn = (self.half_length + self.half_width) * twist.angular;
+        [
+            twist.forward - twist.lateral - turn,
+            twist.forward + twist.lateral + turn,
+            twist.forward + twist.lateral - turn,
+            twist.forward - twist.lateral + turn,
+        ]
+        .map(|rim| rim / self.wheel_radius)
+    }
+}
+
+pub struct MecanumRobot {
+    pub config: MecanumConfig,
+    pub pose: Pose,
+    pub odometry: Pose,
+    pub wheel_velocities: [f32; 4], // rad/s as the encoders measure them
+    velocity: HolonomicTwist,       // true velocity over the last step
+    rng: StdRng,
+}
+
+impl MecanumRobot {
+    pub fn new(config: MecanumConfig, initial_pose: Pose) -> Self {
+        MecanumRobot {
+            config,
+            pose: initial_pose,
+            odometry: initial_pose,
+            wheel_velocities: [0.; 4],
+            velocity: HolonomicTwist::default(),
+            rng: rng_from_seed(config.seed),
+        }
+    }
+}
+
+impl MotionModel for MecanumRobot {
+    type Command = MecanumCommand;
+
+    fn set_command(&mut self, command: MecanumCommand) {
+        self.wheel_velocities = match command {
+            MecanumCommand::Twist(twist) => self.config.wheel_velocities(twist),
+            MecanumCommand::WheelVelocities(wheels) => wheels,
+        };
+    }
+
+    // The encoders see the wheels turn exactly as commanded, but each wheel slips on the ground
+    // and the rollers give way when driving sideways
+    fn step(&mut self, time_step: f32) {
+        let measured = self.config.body_velocity(self.wheel_velocities);
+        let mut ground = self.wheel_velocities;
+        for wheel in ground.iter_mut() {
+            *wheel *= 1. + sample_noise(self.config.slip_std_dev, &mut self.rng);
+        }
+        let mut velocity = self.config.body_velocity(ground);
+        velocity.lateral *= 1. - self.config.roller_slip;
+        self.velocity = velocity;
+
+        self.pose = integrate_holonomic(
+            self.pose,
+            velocity.forward,
+            velocity.lateral,
+            velocity.angular,
+            time_step,
+        );
+        self.odometry = integrate_holonomic(
+            self.odometry,
+            measured.forward,
+            measured.lateral,
+            measured.angular,
+            time_step,
+        );
+    }
+
+    fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    fn odometry(&self) -> Pose {
+        self.odometry
+    }
+
+    fn velocity(&self) -> (f32, f32, f32) {
+        (
+            self.velocity.forward,
+            self.velocity.lateral,
+            self.velocity.angular,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn assert_twist_eq(actual: HolonomicTwist, expected: HolonomicTwist) {
+        assert_abs_diff_eq!(actual.forward, expected.forward, epsilon = 1e-5);
+        assert_abs_diff_eq!(actual.lateral, expected.lateral, epsilon = 1e-5);
+        assert_abs_diff_eq!(actual.angular, expected.angular, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn wheel_velocities_round_trip() {
+        let config = MecanumConfig {
+            half_length: 0.25,
+            half_width: 0.15,
+            ..MecanumConfig::default()
+        };
+        let twist = HolonomicTwist {
+            forward: 0.4,
+            lateral: -0.3,
+            angular: 1.2,
+        };
+        let wheels = config.wheel_velocities(twist);
+        assert_twist_eq(config.body_velocity(wheels), twist);
+
+        let wheels = [3., -1., 7., 2.];
+        let round_trip = config.wheel_velocities(config.body_velocity(wheels));
+        // four wheels over-determine the three velocities, so only consistent speeds come back
+        let consistent = config.wheel_velocities(config.body_velocity(round_trip));
+        for (a, b) in round_trip.iter().zip(consistent.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn driving_sideways_counter_rotates_the_diagonals() {
+        let config = MecanumConfig::default();
+        let wheels = config.wheel_velocities(HolonomicTwist {
+            lateral: 0.5,
+            ..HolonomicTwist::default()
+        });
+        let speed = 0.5 / config.wheel_radius;
+        for (wheel, expected) in wheels.iter().zip([-speed, speed, speed, -speed].iter()) {
+            assert_abs_diff_eq!(wheel, expected, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn noise_free_robot_drives_the_commanded_twist() {
+        let config = MecanumConfig {
+            slip_std_dev: 0.,
+            roller_slip: 0.,
+            seed: Some(0),
+            ..MecanumConfig::default()
+        };
+        let twist = HolonomicTwist {
+            forward: 0.2,
+            lateral: 0.1,
+            angular: -0.5,
+        };
+        let mut robot = MecanumRobot::new(config, Pose::default());
+        robot.set_command(MecanumCommand::Twist(twist));
+        for _ in 0..10 {
+            robot.step(0.1);
+        }
+        let expected = integrate_holonomic(Pose::default(), 0.2, 0.1, -0.5, 1.);
+        assert_abs_diff_eq!(robot.pose.x, expected.x, epsilon = 1e-4);
+        assert_abs_diff_eq!(robot.pose.y, expected.y, epsilon = 1e-4);
+        assert_abs_diff_eq!(robot.pose.theta, expected.theta, epsilon = 1e-4);
+        assert_abs_diff_eq!(robot.odometry.x, robot.pose.x, epsilon = 1e-5);
+    }
+}
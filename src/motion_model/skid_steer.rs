@@ -0,0 +1,144 @@
+// Tracked or four wheel skid steer base. Turning drags the wheels sideways, so it turns as a
+// differential drive with a wider effective track, track_width * icr_factor, following
+// Experimental Kinematics for Wheeled Skid-Steer Mobile Robots by A. Mandow et al.
+use super::{rng_from_seed, sample_noise, MotionModel};
+use crate::diff_drive::{body_velocity, integrate, wheel_velocities, Pose, Twist};
+use rand::rngs::StdRng;
+
+#[derive(Debug, Copy, Clone)]
+pub struct SkidSteerConfig {
+    pub track_width: f32, // metres between the left and right wheels or tracks
+    pub icr_factor: f32,  // true effective track width as a multiple of track_width
+    pub assumed_icr_factor: f32, // the calibrated value the controller and odometry use
+    pub slip_std_dev: f32, // fraction of each side's travel it spins or skids
+    pub seed: Option<u64>, // None seeds from entropy
+}
+
+impl Default for SkidSteerConfig {
+    fn default() -> Self {
+        SkidSteerConfig {
+            track_width: 0.4,
+            icr_factor: 1.5,
+            assumed_icr_factor: 1.5,
+            slip_std_dev: 0.05,
+            seed: None,
+        }
+    }
+}
+
+pub struct SkidSteerRobot {
+    pub config: SkidSteerConfig,
+    pub pose: Pose,
+    pub odometry: Pose,
+    pub side_velocities: (f32, f32), // left and right rim speeds in m/s
+    velocity: (f32, f32),            // true linear and angular velocity over the last step
+    rng: StdRng,
+}
+
+impl SkidSteerRobot {
+    pub fn new(config: SkidSteerConfig, initial_pose: Pose) -> Self {
+        SkidSteerRobot {
+            config,
+            pose: initial_pose,
+            odometry: initial_pose,
+            side_velocities: (0., 0.),
+            velocity: (0., 0.),
+            rng: rng_from_seed(config.seed),
+        }
+    }
+}
+
+impl MotionModel for SkidSteerRobot {
+    type Command = Twist;
+
+    // side speeds for the twist, as far as the calibrated icr factor is right
+    fn set_command(&mut self, command: Twist) {
+        let track = self.config.track_width * self.config.assumed_icr_factor;
+        self.side_velocities = wheel_velocities(command.linear, command.angular, track);
+    }
+
+    // Each side slips on the ground, and odometry turns at the rate the calibration predicts
+    fn step(&mut self, time_step: f32) {
+        let config = self.config;
+        let (left, right) = self.side_velocities;
+        let left_ground = left * (1. + sample_noise(config.slip_std_dev, &mut self.rng));
+        let right_ground = right * (1. + sample_noise(config.slip_std_dev, &mut self.rng));
+        let (linear_vel, angular_vel) = body_velocity(
+            left_ground,
+            right_ground,
+            config.track_width * config.icr_factor,
+        );
+        self.velocity = (linear_vel, angular_vel);
+        self.pose = integrate(self.pose, linear_vel, angular_vel, time_step);
+
+        let (linear_vel, angular_vel) =
+            body_velocity(left, right, config.track_width * config.assumed_icr_factor);
+        self.odometry = integrate(self.odometry, linear_vel, angular_vel, time_step);
+    }
+
+    fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    fn odometry(&self) -> Pose {
+        self.odometry
+    }
+
+    fn velocity(&self) -> (f32, f32, f32) {
+        (self.velocity.0, 0., self.velocity.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn drive(icr_factor: f32, assumed_icr_factor: f32) -> SkidSteerRobot {
+        let config = SkidSteerConfig {
+            icr_factor,
+            assumed_icr_factor,
+            slip_std_dev: 0.,
+            seed: Some(0),
+            ..SkidSteerConfig::default()
+        };
+        let mut robot = SkidSteerRobot::new(config, Pose::default());
+        robot.set_command(Twist {
+            linear: 0.3,
+            angular: 0.5,
+        });
+        for _ in 0..20 {
+            robot.step(0.1);
+        }
+        robot
+    }
+
+    #[test]
+    fn calibrated_odometry_matches_the_true_pose() {
+        let robot = drive(1.5, 1.5);
+        assert_abs_diff_eq!(robot.pose.theta, 1., epsilon = 1e-5);
+        assert_abs_diff_eq!(robot.odometry.x, robot.pose.x, epsilon = 1e-5);
+        assert_abs_diff_eq!(robot.odometry.y, robot.pose.y, epsilon = 1e-5);
+        assert_abs_diff_eq!(robot.odometry.theta, robot.pose.theta, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn underestimated_icr_factor_makes_odometry_over_turn() {
+        // the sides differ by enough to turn at 0.5 rad/s with a track 1.2 times the real one,
+        // but the true effective track is 1.5 times it, so the robot turns at 0.5 * 1.2 / 1.5
+        let robot = drive(1.5, 1.2);
+        let (linear, _, angular) = robot.velocity();
+        assert_abs_diff_eq!(linear, 0.3, epsilon = 1e-6);
+        assert_abs_diff_eq!(angular, 0.4, epsilon = 1e-5);
+        assert_abs_diff_eq!(robot.pose.theta, 0.8, epsilon = 1e-4);
+        assert_abs_diff_eq!(robot.odometry.theta, 1., epsilon = 1e-4);
+
+        // both drove 0.6 m along their arcs, so only the curvature differs
+        let true_end = integrate(Pose::default(), 0.3, 0.4, 2.);
+        assert_abs_diff_eq!(robot.pose.x, true_end.x, epsilon = 1e-4);
+        assert_abs_diff_eq!(robot.pose.y, true_end.y, epsilon = 1e-4);
+        let believed_end = integrate(Pose::default(), 0.3, 0.5, 2.);
+        assert_abs_diff_eq!(robot.odometry.x, believed_end.x, epsilon = 1e-4);
+        assert_abs_diff_eq!(robot.odometry.y, believed_end.y, epsilon = 1e-4);
+    }
+}
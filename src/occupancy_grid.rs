@@ -0,0 +1,288 @@
+// Occupancy grid mapping with the log-odds update from Probabilistic Robotics by S. Thrun et al.
+// (ch. 9)
+use crate::diff_drive::Pose;
+use crate::laser_scan::LaserScan;
+use crate::lidar::{GridGeometry, PixelCoord};
+use ndarray::Array2;
+
+pub mod map_file;
+
+#[derive(Copy, Clone, Debug)]
+pub struct OccupancyGridConfig {
+    pub p_hit: f32,  // occupancy probability of the cell a beam ends in
+    pub p_miss: f32, // occupancy probability of cells a beam passes through
+    pub p_prior: f32,
+    pub p_min: f32, // clamping keeps cells able to change when the world does
+    pub p_max: f32,
+    pub occupied_threshold: f32,
+    pub free_threshold: f32,
+    pub clear_on_no_return: bool, // beams with no return mark cells free out to the maximum range
+}
+
+impl Default for OccupancyGridConfig {
+    // values commonly used with ROS mapping packages
+    fn default() -> Self {
+        OccupancyGridConfig {
+            p_hit: 0.7,
+            p_miss: 0.4,
+            p_prior: 0.5,
+            p_min: 0.12,
+            p_max: 0.97,
+            occupied_threshold: 0.65,
+            free_threshold: 0.196,
+            clear_on_no_return: false,
+        }
+    }
+}
+
+impl OccupancyGridConfig {
+    // log odds that cells are clamped between
+    pub fn log_odds_limits(&self) -> (f32, f32) {
+        (log_odds(self.p_min), log_odds(self.p_max))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CellState {
+    Free,
+    Occupied,
+    Unknown,
+}
+
+pub fn log_odds(p: f32) -> f32 {
+    (p / (1. - p)).ln()
+}
+
+pub fn probability(log_odds: f32) -> f32 {
+    1. - 1. / (1. + log_odds.exp())
+}
+
+#[derive(Clone, Debug)]
+pub struct OccupancyGrid {
+    pub geometry: GridGeometry,
+    pub config: OccupancyGridConfig,
+    pub log_odds: Array2<f32>, // row 0 is the top row, as in GridGeometry
+    limits: (f32, f32),        // log odds of p_min and p_max, fixed when the grid is built
+}
+
+impl OccupancyGrid {
+    pub fn new(geometry: GridGeometry, config: OccupancyGridConfig) -> Self {
+        let shape = (geometry.dimensions.height, geometry.dimensions.width);
+        OccupancyGrid {
+            geometry,
+            config,
+            log_odds: Array2::from_elem(shape, log_odds(config.p_prior)),
+            limits: config.log_odds_limits(),
+        }
+    }
+
+    // forget everything integrated so far
+    pub fn clear(&mut self) {
+        self.log_odds.fill(log_odds(self.config.p_prior));
+    }
+
+    pub fn probability(&self, coord: PixelCoord) -> f32 {
+        probability(self.log_odds[[coord.y, coord.x]])
+    }
+
+    pub fn cell_state(&self, coord: PixelCoord) -> CellState {
+        self.state_of(self.log_odds[[coord.y, coord.x]])
+    }
+
+    pub fn probabilities(&self) -> Array2<f32> {
+        self.log_odds.mapv(probability)
+    }
+
+    pub fn states(&self) -> Array2<CellState> {
+        self.log_odds.mapv(|l| self.state_of(l))
+    }
+
+    // cells above the occupied threshold, e.g. to build a lidar::Environment
+    pub fn occupied(&self) -> Array2<bool> {
+        self.log_odds
+            .mapv(|l| self.state_of(l) == CellState::Occupied)
+    }
+
+    fn state_of(&self, log_odds: f32) -> CellState {
+        let p = probability(log_odds);
+        if p > self.config.occupied_threshold {
+            CellState::Occupied
+        } else if p < self.config.free_threshold {
+            CellState::Free
+        } else {
+            CellState::Unknown
+        }
+    }
+
+    // Integrate a scan taken from the given sensor pose. Cells a beam passes through become more
+    // likely free and the cell it ends in more likely occupied.
+    pub fn integrate_scan(&mut self, scan: &LaserScan, sensor_pose: Pose) {
+        self.integrate_scan_weighted(scan, sensor_pose, 1.);
+    }
+
+    // integrate_scan with the updates scaled by weight, a weight of -1 removes a scan integrated
+    // earlier as long as no clamping happened in between
+    pub fn integrate_scan_weighted(&mut self, scan: &LaserScan, sensor_pose: Pose, weight: f32) {
+        let (geometry, config) = (self.geometry, self.config);
+        scan_updates(
+            &geometry,
+            &config,
+            scan,
+            sensor_pose,
+            weight,
+            |cell, delta| self.update_cell(cell, delta),
+        );
+    }
+
+    fn update_cell(&mut self, (col, row): (isize, isize), delta: f32) {
+        let in_bounds = col >= 0
+            && row >= 0
+            && (col as usize) < self.geometry.dimensions.width
+            && (row as usize) < self.geometry.dimensions.height;
+        if !in_bounds {
+            return;
+        }
+        let (l_min, l_max) = self.limits;
+        let cell = &mut self.log_odds[[row as usize, col as usize]];
+        *cell = (*cell + delta).clamp(l_min, l_max);
+    }
+
+    fn continuous_cell(&self, x: f32, y: f32) -> (isize, isize) {
+        continuous_cell(&self.geometry, x, y)
+    }
+}
+
+// Calls update with every cell a scan passes through or ends in, and the change in log odds for
+// it scaled by weight. Cells may lie outside the grid. Shared by every grid that integrates scans.
+pub(crate) fn scan_updates(
+    geometry: &GridGeometry,
+    config: &OccupancyGridConfig,
+    scan: &LaserScan,
+    sensor_pose: Pose,
+    weight: f32,
+    mut update: impl FnMut((isize, isize), f32),
+) {
+    let l_prior = log_odds(config.p_prior);
+    let l_miss = weight * (log_odds(config.p_miss) - l_prior);
+    let l_hit = weight * (log_odds(config.p_hit) - l_prior);
+    let start = continuous_cell(geometry, sensor_pose.x, sensor_pose.y);
+    for (i, range) in scan.ranges.iter().enumerate() {
+        let hit = scan.is_valid(*range);
+        let no_return = !hit && *range >= scan.range_max;
+        if !(hit || no_return && config.clear_on_no_return) {
+            continue;
+        }
+        // a hit lies on the obstacle surface, often exactly on a cell boundary, so nudge it
+        // along the beam to land in the obstacle cell rather than the free cell before it
+        let length = if hit {
+            *range + 0.01 * geometry.resolution
+        } else {
+            scan.range_max
+        };
+        let angle = sensor_pose.theta + scan.angle(i);
+        let end = continuous_cell(
+            geometry,
+            sensor_pose.x + length * angle.cos(),
+            sensor_pose.y + length * angle.sin(),
+        );
+        for cell in line_cells(start, end) {
+            let delta = if hit && cell == end { l_hit } else { l_miss };
+            update(cell, delta);
+        }
+    }
+}
+
+// cell containing a world point, which may lie outside the grid
+pub(crate) fn continuous_cell(geometry: &GridGeometry, x: f32, y: f32) -> (isize, isize) {
+    let (grid_x, grid_y) = geometry.world_to_continuous(x, y);
+    (grid_x.floor() as isize, grid_y.floor() as isize)
+}
+
+// cells from start to end inclusive, using Bresenham's line algorithm
+fn line_cells(start: (isize, isize), end: (isize, isize)) -> LineCells {
+    let dx = (end.0 - start.0).abs();
+    let dy = -(end.1 - start.1).abs();
+    LineCells {
+        next: Some(start),
+        end,
+        dx,
+        dy,
+        step_x: if start.0 < end.0 { 1 } else { -1 },
+        step_y: if start.1 < end.1 { 1 } else { -1 },
+        error: dx + dy,
+    }
+}
+
+struct LineCells {
+    next: Option<(isize, isize)>, // None once the end has been returned
+    end: (isize, isize),
+    dx: isize,
+    dy: isize,
+    step_x: isize,
+    step_y: isize,
+    error: isize,
+}
+
+impl Iterator for LineCells {
+    type Item = (isize, isize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut x, mut y) = self.next?;
+        if (x, y) == self.end {
+            self.next = None;
+            return Some((x, y));
+        }
+        let error2 = 2 * self.error;
+        if error2 >= self.dy {
+            self.error += self.dy;
+            x += self.step_x;
+        }
+        if error2 <= self.dx {
+            self.error += self.dx;
+            y += self.step_y;
+        }
+        self.next.replace((x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::Dimension;
+
+    #[test]
+    fn line_cells_runs_from_start_to_end() {
+        assert_eq!(line_cells((3, -2), (3, -2)).collect::<Vec<_>>(), [(3, -2)]);
+        for &end in [(7, 2), (-5, 3), (2, -9), (-4, -4), (0, 6), (-8, 0)].iter() {
+            let cells: Vec<_> = line_cells((1, 1), end).collect();
+            let (dx, dy) = (end.0 - 1, end.1 - 1);
+            assert_eq!(cells.len() as isize, dx.abs().max(dy.abs()) + 1);
+            assert_eq!(cells[0], (1, 1));
+            assert_eq!(*cells.last().unwrap(), end);
+            // each step moves to one of the 8 neighbouring cells
+            for pair in cells.windows(2) {
+                let step = (pair[1].0 - pair[0].0, pair[1].1 - pair[0].1);
+                assert!(step.0.abs() <= 1 && step.1.abs() <= 1 && step != (0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn updates_are_clamped_to_the_limits() {
+        let dimensions = Dimension {
+            width: 4,
+            height: 4,
+        };
+        let config = OccupancyGridConfig::default();
+        let mut grid = OccupancyGrid::new(GridGeometry::centred(dimensions, 0.1), config);
+        for _ in 0..100 {
+            grid.update_cell((1, 2), 1.);
+            grid.update_cell((2, 1), -1.);
+        }
+        // outside the grid
+        grid.update_cell((4, 0), 1.);
+        assert_eq!(grid.log_odds[[2, 1]], log_odds(config.p_max));
+        assert_eq!(grid.log_odds[[1, 2]], log_odds(config.p_min));
+        assert_eq!(grid.log_odds[[0, 3]], log_odds(config.p_prior));
+    }
+}
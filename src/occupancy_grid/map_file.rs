@@ -0,0 +1,235 @@
+// Saving occupancy grids as ROS map_server maps (PGM + YAML) or colour PNG images, and loading
+// map_server maps back into occupancy grids
+use super::{line_cells, CellState, OccupancyGrid, OccupancyGridConfig};
+use crate::diff_drive::Pose;
+use crate::lidar::map::{ros_occupancy, MapLoadError, MapMetadata};
+use crate::lidar::{Dimension, GridGeometry};
+use crate::pose_graph::PoseGraph;
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+// trinary pixel values and thresholds written by ROS map_saver
+const OCCUPIED_PIXEL: u8 = 0;
+const FREE_PIXEL: u8 = 254;
+const UNKNOWN_PIXEL: u8 = 205;
+const OCCUPIED_THRESH: f32 = 0.65;
+const FREE_THRESH: f32 = 0.196;
+
+// colours of the PNG rendering
+const UNKNOWN_COLOUR: Rgb<u8> = Rgb([150, 170, 200]);
+const TRAJECTORY_COLOUR: Rgb<u8> = Rgb([220, 30, 30]);
+const NODE_COLOUR: Rgb<u8> = Rgb([255, 140, 0]);
+
+// Save the grid the way map_saver does: a trinary PGM next to the yaml file, with the same name.
+// Cells are classified with the grid's own thresholds, the yaml gets map_saver's thresholds so
+// the pixel values read back as the same states.
+pub fn save_ros_map(grid: &OccupancyGrid, yaml_path: &Path) -> Result<(), MapLoadError> {
+    let image_path = yaml_path.with_extension("pgm");
+    let dims = grid.geometry.dimensions;
+    let mut img = GrayImage::new(dims.width as u32, dims.height as u32);
+    for ((row, col), state) in grid.states().indexed_iter() {
+        let value = match state {
+            CellState::Occupied => OCCUPIED_PIXEL,
+            CellState::Free => FREE_PIXEL,
+            CellState::Unknown => UNKNOWN_PIXEL,
+        };
+        img.put_pixel(col as u32, row as u32, Luma([value]));
+    }
+    img.save(&image_path)?;
+
+    let origin = grid.geometry.origin;
+    let metadata = MapMetadata {
+        image: PathBuf::from(image_path.file_name().expect("yaml path has a file name")),
+        resolution: grid.geometry.resolution,
+        origin: [origin.x, origin.y, origin.theta],
+        negate: 0,
+        occupied_thresh: OCCUPIED_THRESH,
+        free_thresh: FREE_THRESH,
+    };
+    serde_yaml::to_writer(File::create(yaml_path)?, &metadata)?;
+    Ok(())
+}
+
+// Load a map_server map as an occupancy grid. Occupied and free cells are set to the config's
+// clamping limits and unknown cells to the prior, so mapping can carry on from a saved map.
+pub fn load_ros_map(
+    yaml_path: &Path,
+    config: OccupancyGridConfig,
+) -> Result<OccupancyGrid, MapLoadError> {
+    let metadata = MapMetadata::load(yaml_path)?;
+    let img = image::open(metadata.image_path(yaml_path))?.to_luma8();
+    let geometry = GridGeometry {
+        dimensions: Dimension {
+            width: img.width() as usize,
+            height: img.height() as usize,
+        },
+        resolution: metadata.resolution,
+        origin: metadata.origin_pose(),
+    };
+    let mut grid = OccupancyGrid::new(geometry, config);
+    let (l_min, l_max) = config.log_odds_limits();
+    for (col, row, pixel) in img.enumerate_pixels() {
+        let occupancy = ros_occupancy(pixel.0[0] as f32, metadata.negate != 0);
+        let cell = &mut grid.log_odds[[row as usize, col as usize]];
+        if occupancy > metadata.occupied_thresh {
+            *cell = l_max;
+        } else if occupancy < metadata.free_thresh {
+            *cell = l_min;
+        }
+    }
+    Ok(grid)
+}
+
+// Colour image of the grid: known cells shaded from white (free) to black (occupied) by
+// probability, unknown cells tinted blue, and optionally the pose graph trajectory drawn on top.
+pub fn render(grid: &OccupancyGrid, pose_graph: Option<&PoseGraph>) -> RgbImage {
+    let dims = grid.geometry.dimensions;
+    let mut img = RgbImage::new(dims.width as u32, dims.height as u32);
+    for ((row, col), l) in grid.log_odds.indexed_iter() {
+        let colour = match grid.state_of(*l) {
+            CellState::Unknown => UNKNOWN_COLOUR,
+            _ => {
+                let shade = (255. * (1. - super::probability(*l))).round() as u8;
+                Rgb([shade, shade, shade])
+            }
+        };
+        img.put_pixel(col as u32, row as u32, colour);
+    }
+
+    if let Some(pose_graph) = pose_graph {
+        let poses: Vec<Pose> = (0..pose_graph.nodes.len())
+            .map(|id| pose_graph.pose(id))
+            .collect();
+        for pair in poses.windows(2) {
+            let start = grid.continuous_cell(pair[0].x, pair[0].y);
+            let end = grid.continuous_cell(pair[1].x, pair[1].y);
+            draw_line(&mut img, start, end, TRAJECTORY_COLOUR);
+        }
+        // nodes as a short line pointing along their heading
+        let heading_length = 3. * grid.geometry.resolution;
+        for pose in poses {
+            let start = grid.continuous_cell(pose.x, pose.y);
+            let end = grid.continuous_cell(
+                pose.x + heading_length * pose.theta.cos(),
+                pose.y + heading_length * pose.theta.sin(),
+            );
+            draw_line(&mut img, start, end, NODE_COLOUR);
+        }
+    }
+    img
+}
+
+pub fn save_png(
+    grid: &OccupancyGrid,
+    pose_graph: Option<&PoseGraph>,
+    path: &Path,
+) -> Result<(), MapLoadError> {
+    render(grid, pose_graph).save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+fn draw_line(img: &mut RgbImage, start: (isize, isize), end: (isize, isize), colour: Rgb<u8>) {
+    for (col, row) in line_cells(start, end) {
+        let in_bounds =
+            col >= 0 && row >= 0 && (col as u32) < img.width() && (row as u32) < img.height();
+        if in_bounds {
+            img.put_pixel(col as u32, row as u32, colour);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::occupancy_grid::log_odds;
+    use std::fs;
+
+    // scratch directory for one test's files
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cram_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // 6 x 4 grid with a band of each cell state
+    fn grid() -> OccupancyGrid {
+        let geometry = GridGeometry {
+            dimensions: Dimension {
+                width: 6,
+                height: 4,
+            },
+            resolution: 0.05,
+            origin: Pose {
+                x: -1.25,
+                y: 0.5,
+                theta: 0.3,
+            },
+        };
+        let config = OccupancyGridConfig::default();
+        let mut grid = OccupancyGrid::new(geometry, config);
+        for ((_, col), cell) in grid.log_odds.indexed_iter_mut() {
+            *cell = match col % 3 {
+                0 => log_odds(0.9),
+                1 => log_odds(0.1),
+                _ => log_odds(0.5),
+            };
+        }
+        grid
+    }
+
+    #[test]
+    fn save_and_load_ros_map_round_trip() {
+        let dir = output_dir("round_trip");
+        let yaml_path = dir.join("map.yaml");
+        let saved = grid();
+        save_ros_map(&saved, &yaml_path).unwrap();
+
+        let metadata = MapMetadata::load(&yaml_path).unwrap();
+        assert_eq!(metadata.occupied_thresh, OCCUPIED_THRESH);
+        assert_eq!(metadata.free_thresh, FREE_THRESH);
+        assert_eq!(metadata.negate, 0);
+
+        let loaded = load_ros_map(&yaml_path, saved.config).unwrap();
+        assert_eq!(loaded.states(), saved.states());
+        assert_eq!(loaded.geometry.resolution, saved.geometry.resolution);
+        let (origin, saved_origin) = (loaded.geometry.origin, saved.geometry.origin);
+        assert_eq!(
+            (origin.x, origin.y, origin.theta),
+            (saved_origin.x, saved_origin.y, saved_origin.theta)
+        );
+
+        // the same files load as a lidar environment with the occupied cells blocking rays
+        let environment = crate::lidar::map::load_ros_map(&yaml_path).unwrap();
+        assert_eq!(environment.grid, saved.occupied());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_ros_map_rejects_bad_metadata() {
+        let dir = output_dir("bad_metadata");
+        let yaml_path = dir.join("map.yaml");
+        save_ros_map(&grid(), &yaml_path).unwrap();
+        let yaml = fs::read_to_string(&yaml_path).unwrap();
+        let config = OccupancyGridConfig::default();
+
+        let bad_resolution = yaml.replace("resolution: 0.05", "resolution: -0.05");
+        fs::write(&yaml_path, bad_resolution).unwrap();
+        assert!(matches!(
+            load_ros_map(&yaml_path, config),
+            Err(MapLoadError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            crate::lidar::map::load_ros_map(&yaml_path),
+            Err(MapLoadError::InvalidParameter(_))
+        ));
+
+        let swapped = yaml.replace("free_thresh: 0.196", "free_thresh: 0.9");
+        fs::write(&yaml_path, swapped).unwrap();
+        assert!(matches!(
+            load_ros_map(&yaml_path, config),
+            Err(MapLoadError::InvalidParameter(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
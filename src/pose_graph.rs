@@ -1,5 +1,7 @@
 use crate::diff_drive::Pose;
+use crate::laser_scan::LaserScan;
 use ndarray::prelude::*;
+use std::collections::HashMap;
 
 pub struct Edge {
     source_id: usize,
@@ -10,6 +12,15 @@ pub struct Edge {
 pub struct PoseGraph {
     pub nodes: Vec<Array2<f32>>, // 3x3 homogeneous pose matrix for 2D slam
     pub edges: Vec<Edge>,
+    pub scans: HashMap<usize, LaserScan>, // keyframe scans by node index
+}
+
+pub fn node_to_pose(node: &Array2<f32>) -> Pose {
+    Pose {
+        x: node[[0, 2]],
+        y: node[[1, 2]],
+        theta: node[[1, 0]].atan2(node[[0, 0]]),
+    }
 }
 
 impl PoseGraph {
@@ -37,4 +48,14 @@ impl PoseGraph {
 
 		self.edges.push(edge);
     }
+
+    // add a node that carries the scan taken there, so the map can be rebuilt after optimisation
+    pub fn add_keyframe(&mut self, pose: Pose, scan: LaserScan) {
+        self.add_measurement(pose);
+        self.scans.insert(self.nodes.len() - 1, scan);
+    }
+
+    pub fn pose(&self, node_id: usize) -> Pose {
+        node_to_pose(&self.nodes[node_id])
+    }
 }
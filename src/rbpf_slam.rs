@@ -0,0 +1,491 @@
+// Grid-based FastSLAM with a Rao-Blackwellised particle filter, in the style of GMapping from
+// G. Grisetti et al., "Improved Techniques for Grid Mapping with Rao-Blackwellized Particle
+// Filters". Each particle is a robot pose with its own occupancy grid. The odometry proposal is
+// refined by matching the scan against the particle's map, and particles are only resampled when
+// the effective sample size drops. Maps are stored in tiles shared between particles until one of
+// them writes to a tile, so resampling copies pointers rather than whole grids.
+use crate::diff_drive::{normalise_angle, OdometryMotionModel, Pose};
+use crate::laser_scan::LaserScan;
+use crate::lidar::{Dimension, GridGeometry};
+use crate::mcl::low_variance_resample;
+use crate::occupancy_grid::{continuous_cell, log_odds, scan_updates};
+use crate::occupancy_grid::{OccupancyGrid, OccupancyGridConfig};
+use ndarray::Array2;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+const TILE_SIZE: usize = 32; // cells along each side of a map tile
+
+// Log-odds occupancy grid stored as copy-on-write tiles, untouched tiles are not allocated
+#[derive(Clone, Debug)]
+pub struct SharedMap {
+    pub geometry: GridGeometry,
+    pub config: OccupancyGridConfig,
+    tiles: Vec<Option<Arc<Array2<f32>>>>, // row-major, None holds the prior everywhere
+    tile_cols: usize,
+    limits: (f32, f32), // log odds of p_min and p_max, fixed when the map is built
+}
+
+impl SharedMap {
+    pub fn new(geometry: GridGeometry, config: OccupancyGridConfig) -> Self {
+        let tile_cols = geometry.dimensions.width.div_ceil(TILE_SIZE);
+        let tile_rows = geometry.dimensions.height.div_ceil(TILE_SIZE);
+        SharedMap {
+            geometry,
+            config,
+            tiles: vec![None; tile_cols * tile_rows],
+            tile_cols,
+            limits: config.log_odds_limits(),
+        }
+    }
+
+    // log odds of a cell, the prior outside the map
+    pub fn log_odds(&self, (col, row): (isize, isize)) -> f32 {
+        match self.tile_index((col, row)) {
+            Some(index) => match &self.tiles[index] {
+                Some(tile) => tile[[row as usize % TILE_SIZE, col as usize % TILE_SIZE]],
+                None => log_odds(self.config.p_prior),
+            },
+            None => log_odds(self.config.p_prior),
+        }
+    }
+
+    pub fn integrate_scan(&mut self, scan: &LaserScan, sensor_pose: Pose) {
+        let (geometry, config) = (self.geometry, self.config);
+        scan_updates(&geometry, &config, scan, sensor_pose, 1., |cell, delta| {
+            self.update_cell(cell, delta)
+        });
+    }
+
+    // tiles allocated for this map, some of which may be shared with other maps
+    pub fn allocated_tiles(&self) -> usize {
+        self.tiles.iter().filter(|tile| tile.is_some()).count()
+    }
+
+    pub fn to_occupancy_grid(&self) -> OccupancyGrid {
+        let mut grid = OccupancyGrid::new(self.geometry, self.config);
+        grid.log_odds = Array2::from_shape_fn(grid.log_odds.dim(), |(row, col)| {
+            self.log_odds((col as isize, row as isize))
+        });
+        grid
+    }
+
+    fn update_cell(&mut self, (col, row): (isize, isize), delta: f32) {
+        let index = match self.tile_index((col, row)) {
+            Some(index) => index,
+            None => return,
+        };
+        let prior = log_odds(self.config.p_prior);
+        let tile = self.tiles[index]
+            .get_or_insert_with(|| Arc::new(Array2::from_elem((TILE_SIZE, TILE_SIZE), prior)));
+        let (l_min, l_max) = self.limits;
+        // copies the tile first if another map still shares it
+        let cell = &mut Arc::make_mut(tile)[[row as usize % TILE_SIZE, col as usize % TILE_SIZE]];
+        *cell = (*cell + delta).clamp(l_min, l_max);
+    }
+
+    fn tile_index(&self, (col, row): (isize, isize)) -> Option<usize> {
+        let Dimension { width, height } = self.geometry.dimensions;
+        let in_bounds = col >= 0 && row >= 0 && (col as usize) < width && (row as usize) < height;
+        if !in_bounds {
+            return None;
+        }
+        Some(row as usize / TILE_SIZE * self.tile_cols + col as usize / TILE_SIZE)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RbpfConfig {
+    pub particles: usize,
+    pub motion_model: OdometryMotionModel,
+    pub map: OccupancyGridConfig,
+    pub sensor_mount: Pose,       // pose of the lidar on the robot
+    pub max_beams: usize,         // beams used for matching and weighting, evenly spaced
+    pub match_sigma: f32,         // metres, spread of end points about the nearest occupied cell
+    pub match_kernel: usize,      // cells searched around each end point for an occupied cell
+    pub linear_step: f32,         // initial step of the scan matcher's search, metres
+    pub angular_step: f32,        // radians
+    pub refinements: usize,       // times the search steps are halved before it stops
+    pub min_match_score: f32,     // mean per-beam match score (0-1) below which odometry is used
+    pub likelihood_gain: f32,     // divides the scan log likelihood, smoothing the particle weights
+    pub resample_threshold: f32,  // resample when the effective sample size is below this fraction
+    pub update_min_distance: f32, // odometry must move this far or turn update_min_angle to update
+    pub update_min_angle: f32,
+    pub seed: Option<u64>, // None seeds from entropy
+}
+
+impl Default for RbpfConfig {
+    // close to the GMapping defaults, with more frequent updates for the small simulated maps
+    fn default() -> Self {
+        RbpfConfig {
+            particles: 30,
+            motion_model: OdometryMotionModel {
+                alpha1: 0.1,
+                alpha2: 0.1,
+                alpha3: 0.1,
+                alpha4: 0.1,
+            },
+            map: OccupancyGridConfig::default(),
+            sensor_mount: Pose::default(),
+            max_beams: 90,
+            match_sigma: 0.05,
+            match_kernel: 1,
+            linear_step: 0.05,
+            angular_step: 0.05,
+            refinements: 5,
+            min_match_score: 0.3,
+            likelihood_gain: 3.,
+            resample_threshold: 0.5,
+            update_min_distance: 0.2,
+            update_min_angle: 0.2,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RbpfParticle {
+    pub pose: Pose,
+    pub weight: f64,
+    pub map: SharedMap,
+}
+
+pub struct RbpfSlam {
+    pub config: RbpfConfig,
+    pub particles: Vec<RbpfParticle>,
+    last_odometry: Option<Pose>, // odometry at the last filter update
+    rng: StdRng,
+}
+
+impl RbpfSlam {
+    // every particle starts at the initial pose, which fixes the map frame
+    pub fn new(geometry: GridGeometry, initial_pose: Pose, config: RbpfConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let particle = RbpfParticle {
+            pose: initial_pose,
+            weight: 1. / config.particles as f64,
+            map: SharedMap::new(geometry, config.map),
+        };
+        RbpfSlam {
+            config,
+            particles: vec![particle; config.particles],
+            last_odometry: None,
+            rng,
+        }
+    }
+
+    // Feed the latest odometry pose and scan. The filter only updates once the odometry has moved
+    // far enough since the last update, returns whether it did.
+    pub fn update(&mut self, odometry: Pose, scan: &LaserScan) -> bool {
+        let last = match self.last_odometry {
+            Some(last) => last,
+            None => {
+                // the particles are identical, so map the first scan once and share it
+                let mount = self.config.sensor_mount;
+                let first = &mut self.particles[0];
+                first.map.integrate_scan(scan, first.pose.compose(mount));
+                let map = first.map.clone();
+                self.particles.iter_mut().for_each(|p| p.map = map.clone());
+                self.last_odometry = Some(odometry);
+                return true;
+            }
+        };
+        let distance = ((odometry.x - last.x).powi(2) + (odometry.y - last.y).powi(2)).sqrt();
+        let angle = normalise_angle(odometry.theta - last.theta).abs();
+        if distance < self.config.update_min_distance && angle < self.config.update_min_angle {
+            return false;
+        }
+        self.last_odometry = Some(odometry);
+
+        let step = (scan.len() / self.config.max_beams.max(1)).max(1);
+        let mut log_weights = Vec::with_capacity(self.particles.len());
+        for i in 0..self.particles.len() {
+            let particle = &self.particles[i];
+            let predicted =
+                self.config
+                    .motion_model
+                    .sample(particle.pose, last, odometry, &mut self.rng);
+            let (matched, score) = self.optimise(&particle.map, scan, predicted, step);
+            let pose = if score >= self.config.min_match_score {
+                matched
+            } else {
+                predicted
+            };
+            let (_, log_likelihood) = self.match_score(&particle.map, scan, pose, step);
+            log_weights
+                .push(particle.weight.ln() + log_likelihood / self.config.likelihood_gain as f64);
+            self.particles[i].pose = pose;
+        }
+        self.set_weights(&log_weights);
+        self.resample_if_degenerate();
+
+        let mount = self.config.sensor_mount;
+        for particle in &mut self.particles {
+            particle
+                .map
+                .integrate_scan(scan, particle.pose.compose(mount));
+        }
+        true
+    }
+
+    pub fn effective_sample_size(&self) -> f64 {
+        1. / self
+            .particles
+            .iter()
+            .map(|p| p.weight * p.weight)
+            .sum::<f64>()
+    }
+
+    pub fn best_particle(&self) -> &RbpfParticle {
+        self.particles
+            .iter()
+            .max_by(|a, b| a.weight.total_cmp(&b.weight))
+            .expect("the filter has particles")
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.best_particle().pose
+    }
+
+    pub fn map(&self) -> OccupancyGrid {
+        self.best_particle().map.to_occupancy_grid()
+    }
+
+    // tiles allocated across all particles, counting shared tiles once
+    pub fn unique_tiles(&self) -> usize {
+        let tiles: HashSet<*const Array2<f32>> = self
+            .particles
+            .iter()
+            .flat_map(|p| p.map.tiles.iter().flatten().map(Arc::as_ptr))
+            .collect();
+        tiles.len()
+    }
+
+    // resample once the effective sample size drops below the threshold, returns whether it did
+    fn resample_if_degenerate(&mut self) -> bool {
+        let threshold = self.config.resample_threshold as f64 * self.particles.len() as f64;
+        if self.effective_sample_size() < threshold {
+            self.resample();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn resample(&mut self) {
+        let weights: Vec<f64> = self.particles.iter().map(|p| p.weight).collect();
+        let count = self.particles.len();
+        let weight = 1. / count as f64;
+        self.particles = low_variance_resample(&weights, count, &mut self.rng)
+            .into_iter()
+            .map(|i| RbpfParticle {
+                weight,
+                ..self.particles[i].clone()
+            })
+            .collect();
+    }
+
+    fn set_weights(&mut self, log_weights: &[f64]) {
+        // subtract the largest before exponentiating so the weights don't all underflow to zero
+        let max = log_weights
+            .iter()
+            .cloned()
+            .fold(f64::NEG_INFINITY, f64::max);
+        for (particle, l) in self.particles.iter_mut().zip(log_weights) {
+            particle.weight = if max.is_finite() { (l - max).exp() } else { 1. };
+        }
+        let total: f64 = self.particles.iter().map(|p| p.weight).sum();
+        self.particles.iter_mut().for_each(|p| p.weight /= total);
+    }
+
+    // Hill climbing from the initial pose over x, y and heading, halving the steps whenever no
+    // neighbour scores better. Returns the best pose and its mean per-beam score.
+    fn optimise(
+        &self,
+        map: &SharedMap,
+        scan: &LaserScan,
+        initial: Pose,
+        step: usize,
+    ) -> (Pose, f32) {
+        let mut best = initial;
+        let (mut best_score, _) = self.match_score(map, scan, best, step);
+        let mut linear = self.config.linear_step;
+        let mut angular = self.config.angular_step;
+        let mut refinements = 0;
+        while refinements < self.config.refinements {
+            let moves = [
+                (linear, 0., 0.),
+                (-linear, 0., 0.),
+                (0., linear, 0.),
+                (0., -linear, 0.),
+                (0., 0., angular),
+                (0., 0., -angular),
+            ];
+            let (candidate, score) = moves
+                .iter()
+                .map(|(dx, dy, dtheta)| {
+                    let pose = Pose {
+                        x: best.x + dx,
+                        y: best.y + dy,
+                        theta: best.theta + dtheta,
+                    };
+                    (pose, self.match_score(map, scan, pose, step).0)
+                })
+                .fold((best, best_score), |a, b| if b.1 > a.1 { b } else { a });
+            if score > best_score {
+                best = candidate;
+                best_score = score;
+            } else {
+                linear /= 2.;
+                angular /= 2.;
+                refinements += 1;
+            }
+        }
+        (best, best_score)
+    }
+
+    // Mean per-beam match score and log likelihood of a scan from a robot pose. Each end point
+    // counts by its distance to the nearest occupied cell within match_kernel cells, end points
+    // with none that close count as just beyond the kernel.
+    fn match_score(
+        &self,
+        map: &SharedMap,
+        scan: &LaserScan,
+        pose: Pose,
+        step: usize,
+    ) -> (f32, f64) {
+        let sensor_pose = pose.compose(self.config.sensor_mount);
+        let resolution = map.geometry.resolution;
+        let sigma2 = self.config.match_sigma.powi(2);
+        let kernel = self.config.match_kernel as isize;
+        let max_distance2 = ((kernel + 1) as f32 * resolution).powi(2);
+        let l_occupied = log_odds(map.config.occupied_threshold);
+
+        let (mut score, mut log_likelihood, mut count) = (0., 0., 0);
+        for (i, range) in scan.ranges.iter().enumerate().step_by(step) {
+            if !scan.is_valid(*range) {
+                continue;
+            }
+            let angle = sensor_pose.theta + scan.angle(i);
+            let (col, row) = continuous_cell(
+                &map.geometry,
+                sensor_pose.x + range * angle.cos(),
+                sensor_pose.y + range * angle.sin(),
+            );
+            let mut distance2 = max_distance2;
+            for dr in -kernel..=kernel {
+                for dc in -kernel..=kernel {
+                    if map.log_odds((col + dc, row + dr)) > l_occupied {
+                        let d2 = (dc * dc + dr * dr) as f32 * resolution * resolution;
+                        distance2 = distance2.min(d2);
+                    }
+                }
+            }
+            if distance2 < max_distance2 {
+                score += (-distance2 / sigma2).exp();
+            }
+            log_likelihood += (-distance2 / (2. * sigma2)) as f64;
+            count += 1;
+        }
+        if count == 0 {
+            return (0., 0.);
+        }
+        (score / count as f32, log_likelihood)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    // 3 x 3 m at 5 cm, 2 x 2 tiles
+    fn geometry() -> GridGeometry {
+        let dimensions = Dimension {
+            width: 60,
+            height: 60,
+        };
+        GridGeometry::centred(dimensions, 0.05)
+    }
+
+    #[test]
+    fn shared_map_copies_a_tile_only_when_writing_to_it() {
+        let mut map = SharedMap::new(geometry(), OccupancyGridConfig::default());
+        for col in 0..60 {
+            map.update_cell((col, 10), 1.);
+            map.update_cell((col, 50), 1.);
+        }
+        assert_eq!(map.allocated_tiles(), 4);
+        let mut copy = map.clone();
+        let shared = |a: &SharedMap, b: &SharedMap| -> Vec<bool> {
+            a.tiles
+                .iter()
+                .zip(&b.tiles)
+                .map(|(a, b)| Arc::ptr_eq(a.as_ref().unwrap(), b.as_ref().unwrap()))
+                .collect()
+        };
+        assert_eq!(shared(&map, &copy), [true; 4]);
+
+        // a cell in the bottom right tile
+        copy.update_cell((40, 40), 2.);
+        assert_eq!(shared(&map, &copy), [true, true, true, false]);
+        assert_abs_diff_eq!(map.log_odds((40, 40)), 0.);
+        assert_abs_diff_eq!(copy.log_odds((40, 40)), 2.);
+        assert_abs_diff_eq!(copy.log_odds((40, 50)), map.log_odds((40, 50)));
+
+        // writing to a tile the other map no longer shares doesn't copy it again
+        let tile = Arc::as_ptr(copy.tiles[3].as_ref().unwrap());
+        copy.update_cell((41, 40), 1.);
+        assert_eq!(Arc::as_ptr(copy.tiles[3].as_ref().unwrap()), tile);
+    }
+
+    #[test]
+    fn resampling_waits_for_the_effective_sample_size_to_drop() {
+        let config = RbpfConfig {
+            particles: 4,
+            seed: Some(0),
+            ..RbpfConfig::default()
+        };
+        let mut slam = RbpfSlam::new(geometry(), Pose::default(), config);
+        for (i, particle) in slam.particles.iter_mut().enumerate() {
+            particle.pose.x = i as f32;
+        }
+
+        // weights giving an effective sample size of about 2.9, then 1.9, against a threshold of 2
+        slam.set_weights(&[0.4f64.ln(), 0.4f64.ln(), 0.1f64.ln(), 0.1f64.ln()]);
+        assert_abs_diff_eq!(slam.effective_sample_size(), 1. / 0.34, epsilon = 1e-9);
+        assert!(!slam.resample_if_degenerate());
+        assert_abs_diff_eq!(slam.particles[0].weight, 0.4, epsilon = 1e-12);
+
+        slam.set_weights(&[0.7f64.ln(), 0.1f64.ln(), 0.1f64.ln(), 0.1f64.ln()]);
+        assert!(slam.effective_sample_size() < 2.);
+        assert!(slam.resample_if_degenerate());
+        for particle in &slam.particles {
+            assert_abs_diff_eq!(particle.weight, 0.25);
+        }
+        // low-variance resampling gives the heaviest particle between 2 and 3 of the 4 copies
+        let copies = slam.particles.iter().filter(|p| p.pose.x == 0.).count();
+        assert!((2..=3).contains(&copies), "{} copies", copies);
+
+        // uniform weights never need resampling
+        assert!(!slam.resample_if_degenerate());
+    }
+
+    #[test]
+    fn best_particle_has_the_largest_weight() {
+        let config = RbpfConfig {
+            particles: 3,
+            seed: Some(0),
+            ..RbpfConfig::default()
+        };
+        let mut slam = RbpfSlam::new(geometry(), Pose::default(), config);
+        slam.particles[1].pose.x = 1.;
+        slam.set_weights(&[-2., -1., -3.]);
+        assert_abs_diff_eq!(slam.pose().x, 1.);
+    }
+}
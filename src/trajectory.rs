@@ -0,0 +1,97 @@
+use crate::diff_drive::Pose;
+
+// Time stamped poses, e.g. ground truth or odometry, that can be queried between samples
+#[derive(Clone, Debug, Default)]
+pub struct PoseTrajectory {
+    pub poses: Vec<(f64, Pose)>, // sorted by time
+}
+
+impl PoseTrajectory {
+    pub fn new() -> Self {
+        PoseTrajectory { poses: vec![] }
+    }
+
+    // add a sample, keeping the samples sorted by time
+    pub fn push(&mut self, time: f64, pose: Pose) {
+        let index = self.poses.partition_point(|(t, _)| *t <= time);
+        self.poses.insert(index, (time, pose));
+    }
+
+    pub fn start_time(&self) -> Option<f64> {
+        self.poses.first().map(|(t, _)| *t)
+    }
+
+    pub fn end_time(&self) -> Option<f64> {
+        self.poses.last().map(|(t, _)| *t)
+    }
+
+    // pose interpolated between the neighbouring samples, None outside the sampled time range
+    pub fn pose_at(&self, time: f64) -> Option<Pose> {
+        let index = self.poses.partition_point(|(t, _)| *t < time);
+        if index == self.poses.len() {
+            return None;
+        }
+        let (t_after, after) = self.poses[index];
+        if t_after == time {
+            return Some(after);
+        }
+        if index == 0 {
+            return None;
+        }
+        let (t_before, before) = self.poses[index - 1];
+        let fraction = ((time - t_before) / (t_after - t_before)) as f32;
+        Some(before.interpolate(after, fraction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use std::f32::consts::PI;
+
+    fn trajectory() -> PoseTrajectory {
+        let mut trajectory = PoseTrajectory::new();
+        // pushed out of order, push keeps them sorted
+        trajectory.push(
+            2.,
+            Pose {
+                x: 3.,
+                y: 1.,
+                theta: -PI + 0.1,
+            },
+        );
+        trajectory.push(
+            1.,
+            Pose {
+                x: 1.,
+                y: 1.,
+                theta: PI - 0.1,
+            },
+        );
+        trajectory
+    }
+
+    #[test]
+    fn pose_at_is_none_outside_the_samples() {
+        let trajectory = trajectory();
+        assert_eq!(trajectory.start_time(), Some(1.));
+        assert_eq!(trajectory.end_time(), Some(2.));
+        assert!(trajectory.pose_at(0.999).is_none());
+        assert!(trajectory.pose_at(2.001).is_none());
+        assert!(PoseTrajectory::new().pose_at(1.).is_none());
+    }
+
+    #[test]
+    fn pose_at_returns_samples_and_interpolates_between_them() {
+        let trajectory = trajectory();
+        assert_abs_diff_eq!(trajectory.pose_at(1.).unwrap().x, 1.);
+        assert_abs_diff_eq!(trajectory.pose_at(2.).unwrap().x, 3.);
+
+        let pose = trajectory.pose_at(1.25).unwrap();
+        assert_abs_diff_eq!(pose.x, 1.5, epsilon = 1e-6);
+        assert_abs_diff_eq!(pose.y, 1., epsilon = 1e-6);
+        // the heading turns the short way through pi rather than back through 0
+        assert_abs_diff_eq!(pose.theta, PI - 0.05, epsilon = 1e-6);
+    }
+}
@@ -1,12 +1,77 @@
 #![allow(clippy::many_single_char_names)]
+use ndarray::linalg::general_mat_mul;
+#[cfg(feature = "rayon")]
+use ndarray::parallel::prelude::*;
 use ndarray::prelude::*;
 
+// clouds with at least this many rows are split across threads when the rayon feature is enabled
+#[cfg(feature = "rayon")]
+pub const PARALLEL_ROW_THRESHOLD: usize = 100_000;
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK_ROWS: usize = 8_192;
+// rows transform_cloud_in_place multiplies at a time into its buffer
+const IN_PLACE_CHUNK_ROWS: usize = 1_024;
+
 pub fn angle_to_rmat(theta: f64) -> Array2<f64> {
     let c = theta.cos();
     let s = theta.sin();
     array![[c, -s], [s, c]]
 }
 
+// rotation about the x axis by theta radians (3D)
+pub fn rotation_x(theta: f64) -> Array2<f64> {
+    let c = theta.cos();
+    let s = theta.sin();
+    array![[1., 0., 0.], [0., c, -s], [0., s, c]]
+}
+
+// rotation about the y axis by theta radians (3D)
+pub fn rotation_y(theta: f64) -> Array2<f64> {
+    let c = theta.cos();
+    let s = theta.sin();
+    array![[c, 0., s], [0., 1., 0.], [-s, 0., c]]
+}
+
+// rotation about the z axis by theta radians (3D)
+pub fn rotation_z(theta: f64) -> Array2<f64> {
+    let c = theta.cos();
+    let s = theta.sin();
+    array![[c, -s, 0.], [s, c, 0.], [0., 0., 1.]]
+}
+
+// 3D rotation from roll, pitch and yaw, applied in that order about the fixed x, y and z axes
+pub fn roll_pitch_yaw_to_rmat(roll: f64, pitch: f64, yaw: f64) -> Array2<f64> {
+    rotation_z(yaw)
+        .dot(&rotation_y(pitch))
+        .dot(&rotation_x(roll))
+}
+
+// inverse of roll_pitch_yaw_to_rmat, returns (roll, pitch, yaw)
+pub fn rmat_to_roll_pitch_yaw(rmat: &Array2<f64>) -> (f64, f64, f64) {
+    let pitch = (-rmat[[2, 0]]).clamp(-1., 1.).asin();
+    if rmat[[2, 0]].abs() < 1. - 1e-9 {
+        let roll = rmat[[2, 1]].atan2(rmat[[2, 2]]);
+        let yaw = rmat[[1, 0]].atan2(rmat[[0, 0]]);
+        (roll, pitch, yaw)
+    } else {
+        // gimbal lock: roll and yaw are coupled, so attribute all of it to yaw
+        let yaw = (-rmat[[0, 1]]).atan2(rmat[[1, 1]]);
+        (0., pitch, yaw)
+    }
+}
+
+// 3D rotation by angle radians about the given axis (Rodrigues' formula), the axis need not be
+// unit length
+pub fn axis_angle_to_rmat(axis: &Array1<f64>, angle: f64) -> Array2<f64> {
+    let axis = axis / axis.dot(axis).sqrt();
+    let k = array![
+        [0., -axis[2], axis[1]],
+        [axis[2], 0., -axis[0]],
+        [-axis[1], axis[0], 0.],
+    ];
+    Array2::eye(3) + angle.sin() * &k + (1. - angle.cos()) * k.dot(&k)
+}
+
 pub fn rmat_and_tvec_to_tmat(rmat: &Array2<f64>, tvec: &Array1<f64>) -> Array2<f64> {
     let mut t = Array::zeros((rmat.nrows() + 1, rmat.ncols() + 1));
     t.slice_mut(s![..rmat.nrows(), ..rmat.ncols()])
@@ -16,12 +81,175 @@ pub fn rmat_and_tvec_to_tmat(rmat: &Array2<f64>, tvec: &Array1<f64>) -> Array2<f
     t
 }
 
+// inverse of rmat_and_tvec_to_tmat, works for both 2D (3x3) and 3D (4x4) transforms
+pub fn tmat_to_rmat_and_tvec(tmat: &Array2<f64>) -> (Array2<f64>, Array1<f64>) {
+    let n = tmat.nrows() - 1;
+    let rmat = tmat.slice(s![..n, ..n]).to_owned();
+    let tvec = tmat.slice(s![..n, n]).to_owned();
+    (rmat, tvec)
+}
+
+// input: cloud of homogeneous points, one per row (dimension nxm), and transform (dimension mxm)
+// output: transformed cloud, computed as a single matrix product cloud * tmat^T
 pub fn transformed_cloud(cloud_ref: &Array2<f64>, tmat: &Array2<f64>) -> Array2<f64> {
-    let mut cloud_target = Array2::zeros((cloud_ref.nrows(), cloud_ref.ncols()));
-    for (i, mut row) in cloud_target.axis_iter_mut(Axis(0)).enumerate() {
-        let pt = cloud_ref.slice(s![i, ..]);
-        let new_pt = tmat.dot(&pt);
-        row.assign(&new_pt);
+    #[cfg(feature = "rayon")]
+    {
+        if cloud_ref.nrows() >= PARALLEL_ROW_THRESHOLD {
+            return transformed_cloud_par(cloud_ref, tmat);
+        }
+    }
+    cloud_ref.dot(&tmat.t())
+}
+
+// same as transformed_cloud but overwrites the input cloud, multiplying blocks of rows into a
+// buffer that is reused for every block
+pub fn transform_cloud_in_place(cloud: &mut Array2<f64>, tmat: &Array2<f64>) {
+    let tmat_t = tmat.t();
+    let mut buffer = Array2::zeros((IN_PLACE_CHUNK_ROWS.min(cloud.nrows()), cloud.ncols()));
+    for mut block in cloud.axis_chunks_iter_mut(Axis(0), IN_PLACE_CHUNK_ROWS) {
+        let mut product = buffer.slice_mut(s![..block.nrows(), ..]);
+        general_mat_mul(1., &block, &tmat_t, 0., &mut product);
+        block.assign(&product);
     }
+}
+
+// multi-threaded transformed_cloud, each thread multiplies a contiguous block of rows
+#[cfg(feature = "rayon")]
+pub fn transformed_cloud_par(cloud_ref: &Array2<f64>, tmat: &Array2<f64>) -> Array2<f64> {
+    let mut cloud_target = Array2::zeros((cloud_ref.nrows(), cloud_ref.ncols()));
+    let tmat_t = tmat.t();
     cloud_target
+        .axis_chunks_iter_mut(Axis(0), PARALLEL_CHUNK_ROWS)
+        .into_par_iter()
+        .zip(cloud_ref.axis_chunks_iter(Axis(0), PARALLEL_CHUNK_ROWS))
+        .for_each(|(mut target, source)| {
+            general_mat_mul(1., &source, &tmat_t, 0., &mut target);
+        });
+    cloud_target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use ndarray_linalg::Determinant;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    // the row-by-row product the vectorised transforms replaced
+    fn transformed_cloud_rowwise(cloud_ref: &Array2<f64>, tmat: &Array2<f64>) -> Array2<f64> {
+        let mut cloud_target = Array2::zeros((cloud_ref.nrows(), cloud_ref.ncols()));
+        for (i, mut row) in cloud_target.axis_iter_mut(Axis(0)).enumerate() {
+            row.assign(&tmat.dot(&cloud_ref.slice(s![i, ..])));
+        }
+        cloud_target
+    }
+
+    // homogeneous cloud of n random points with coordinates in [-5, 5)
+    fn homogeneous_cloud(n: usize, dimensions: usize) -> Array2<f64> {
+        let mut rng = StdRng::seed_from_u64(0);
+        Array2::from_shape_fn((n, dimensions + 1), |(_, j)| {
+            if j == dimensions {
+                1.
+            } else {
+                rng.gen_range(-5.0..5.0)
+            }
+        })
+    }
+
+    fn test_transforms() -> Vec<Array2<f64>> {
+        vec![
+            rmat_and_tvec_to_tmat(&angle_to_rmat(0.7), &array![2., -1.]),
+            rmat_and_tvec_to_tmat(&roll_pitch_yaw_to_rmat(0.3, -0.2, 1.1), &array![1., 2., 3.]),
+        ]
+    }
+
+    fn assert_matrix_eq(a: &Array2<f64>, b: &Array2<f64>, epsilon: f64) {
+        assert_eq!(a.dim(), b.dim());
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert_abs_diff_eq!(x, y, epsilon = epsilon);
+        }
+    }
+
+    #[test]
+    fn roll_pitch_yaw_round_trip() {
+        for &(roll, pitch, yaw) in &[
+            (0., 0., 0.),
+            (0.3, -0.2, 0.5),
+            (-2.5, 1.2, 3.),
+            (PI - 0.01, -1.5, -PI + 0.01),
+        ] {
+            let rmat = roll_pitch_yaw_to_rmat(roll, pitch, yaw);
+            let (r, p, y) = rmat_to_roll_pitch_yaw(&rmat);
+            assert_abs_diff_eq!(r, roll, epsilon = 1e-9);
+            assert_abs_diff_eq!(p, pitch, epsilon = 1e-9);
+            assert_abs_diff_eq!(y, yaw, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn roll_pitch_yaw_round_trip_at_gimbal_lock() {
+        // roll and yaw can't be told apart here, but they must still give back the same rotation
+        for &pitch in &[FRAC_PI_2, -FRAC_PI_2, FRAC_PI_2 - 1e-7, -FRAC_PI_2 + 1e-7] {
+            let rmat = roll_pitch_yaw_to_rmat(0.4, pitch, -0.7);
+            let (r, p, y) = rmat_to_roll_pitch_yaw(&rmat);
+            assert_abs_diff_eq!(p, pitch, epsilon = 1e-6);
+            assert_matrix_eq(&roll_pitch_yaw_to_rmat(r, p, y), &rmat, 1e-6);
+        }
+    }
+
+    #[test]
+    fn axis_angle_gives_proper_rotation() {
+        for (axis, angle) in [
+            (array![0., 0., 2.], 0.8),
+            (array![1., 1., 0.], -2.),
+            (array![0.3, -4., 1.5], 3.),
+        ] {
+            let rmat = axis_angle_to_rmat(&axis, angle);
+            assert_matrix_eq(&rmat.dot(&rmat.t()), &Array2::eye(3), 1e-12);
+            assert_abs_diff_eq!(rmat.det().unwrap(), 1., epsilon = 1e-12);
+            // the axis is left where it is
+            let rotated_axis = rmat.dot(&axis);
+            for (a, b) in rotated_axis.iter().zip(axis.iter()) {
+                assert_abs_diff_eq!(a, b, epsilon = 1e-12);
+            }
+        }
+        assert_matrix_eq(
+            &axis_angle_to_rmat(&array![0., 0., 2.], 0.8),
+            &rotation_z(0.8),
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn transformed_cloud_matches_row_loop() {
+        for tmat in test_transforms() {
+            // includes a cloud whose length is not a multiple of the in-place block size
+            for &n in &[0, 1, 7, IN_PLACE_CHUNK_ROWS, 2 * IN_PLACE_CHUNK_ROWS + 5] {
+                let cloud = homogeneous_cloud(n, tmat.nrows() - 1);
+                let expected = transformed_cloud_rowwise(&cloud, &tmat);
+                assert_matrix_eq(&transformed_cloud(&cloud, &tmat), &expected, 1e-12);
+                let mut in_place = cloud.clone();
+                transform_cloud_in_place(&mut in_place, &tmat);
+                assert_matrix_eq(&in_place, &expected, 1e-12);
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn transformed_cloud_par_matches_row_loop() {
+        for tmat in test_transforms() {
+            for &n in &[0, 1, PARALLEL_CHUNK_ROWS + 3, PARALLEL_ROW_THRESHOLD + 17] {
+                let cloud = homogeneous_cloud(n, tmat.nrows() - 1);
+                let expected = transformed_cloud_rowwise(&cloud, &tmat);
+                assert_matrix_eq(&transformed_cloud_par(&cloud, &tmat), &expected, 1e-12);
+                // large enough to take the parallel path
+                if n >= PARALLEL_ROW_THRESHOLD {
+                    assert_matrix_eq(&transformed_cloud(&cloud, &tmat), &expected, 1e-12);
+                }
+            }
+        }
+    }
 }